@@ -2,17 +2,21 @@ extern crate jd4_5;
 
 use std::path::PathBuf;
 use std::process;
-use jd4_5::sandbox::{self, Sandbox};
+use jd4_5::sandbox::{ExecuteOptions, Sandbox};
+use jd4_5::verdict::ExitStatus;
 
 fn main() {
-    let mut sandbox = Sandbox::new();
-    let status = sandbox.execute(
+    let mut sandbox = Sandbox::new().unwrap();
+    let result = sandbox.execute(
         PathBuf::from("/bin/bash"),
         Box::new([String::from("bunny")]),
-        sandbox::default_envs(),
         PathBuf::from("/"),
         Box::new([]),
-        None).unwrap();
+        Box::new([]),
+        ExecuteOptions::default()).unwrap();
     drop(sandbox);
-    process::exit(status);
+    process::exit(match result.exit_status {
+        ExitStatus::Exited(code) => code,
+        ExitStatus::Signaled(signal) => 128 + signal,
+    });
 }
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use hex;
+use sha2::{Digest, Sha256};
+use bincode;
+use compile::{CompileError, Compiler, Files, Source, Target};
+use sandbox::Sandbox;
+use tempdir::TempDir;
+use util::Pool;
+
+// Compiled packages stored on disk, keyed by a hash of the language id, the
+// compiler config and the source. Each entry is a directory named by its key.
+// The least recently used entries are evicted to keep the total size bounded.
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<Index>,
+}
+
+// Compiles through the cache if there is one.
+pub struct CachedCompiler<'a> {
+    compiler: &'a dyn Compiler,
+    fingerprint: &'a [u8],
+    cache: Option<&'a Cache>,
+}
+
+struct Index {
+    entries: HashMap<String, Entry>,
+    size: u64,
+    clock: u64,
+}
+
+struct Entry {
+    size: u64,
+    last_used: u64,
+    // Loads in progress, during which the entry is not evicted.
+    pins: usize,
+}
+
+const TEMP_PREFIX: &str = "tmp";
+
+impl Cache {
+    // Picks up entries left in dir by a previous run, oldest first.
+    pub fn new(dir: PathBuf, max_size: u64) -> io::Result<Cache> {
+        fs::create_dir_all(&dir)?;
+        let mut found = Vec::new();
+        for result in fs::read_dir(&dir)? {
+            let entry = result?;
+            let path = entry.path();
+            let key = entry.file_name().to_string_lossy().into_owned();
+            if key.starts_with(TEMP_PREFIX) {
+                fs::remove_dir_all(&path)?;
+                continue;
+            }
+            let modified = entry.metadata()?.modified()?;
+            found.push((modified, key, dir_size(&path)?));
+        }
+        found.sort();
+        let mut index = Index {
+            entries: HashMap::new(),
+            size: 0,
+            clock: 0,
+        };
+        for (_, key, size) in found {
+            index.insert(key, size);
+        }
+        let cache = Cache { dir, max_size, index: Mutex::new(index) };
+        cache.evict(&mut cache.index.lock().unwrap())?;
+        Ok(cache)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().size
+    }
+
+    pub fn get(&self, key: &str) -> Option<Target> {
+        {
+            let mut index = self.index.lock().unwrap();
+            let clock = index.tick();
+            let entry = index.entries.get_mut(key)?;
+            entry.last_used = clock;
+            entry.pins += 1;
+        }
+        // Loading copies the package, which is done without the lock. The
+        // pin keeps the entry from being evicted meanwhile.
+        let loaded = Target::load(&self.dir.join(key));
+        let mut index = self.index.lock().unwrap();
+        let pins = index.entries.get_mut(key).map_or(0, |entry| {
+            entry.pins -= 1;
+            entry.pins
+        });
+        if loaded.is_err() && pins == 0 {
+            let _ = self.remove(&mut index, key);
+        }
+        // Entries may have been kept over the size while pinned.
+        let _ = self.evict(&mut index);
+        loaded.ok()
+    }
+
+    pub fn put(&self, key: &str, target: &Target) -> io::Result<()> {
+        let temp_dir = TempDir::new_in(&self.dir, TEMP_PREFIX)?;
+        let entry_dir = temp_dir.path().join("entry");
+        target.save(&entry_dir)?;
+        let size = dir_size(&entry_dir)?;
+        if size > self.max_size {
+            return Ok(());
+        }
+        let mut index = self.index.lock().unwrap();
+        if index.entries.contains_key(key) {
+            return Ok(());
+        }
+        fs::rename(&entry_dir, self.dir.join(key))?;
+        index.insert(String::from(key), size);
+        self.evict(&mut index)
+    }
+
+    fn evict(&self, index: &mut Index) -> io::Result<()> {
+        while index.size > self.max_size {
+            let key = match index.entries.iter()
+                .filter(|&(_, entry)| entry.pins == 0)
+                .min_by_key(|&(_, entry)| entry.last_used) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            self.remove(index, &key)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, index: &mut Index, key: &str) -> io::Result<()> {
+        if let Some(entry) = index.entries.remove(key) {
+            index.size -= entry.size;
+        }
+        fs::remove_dir_all(self.dir.join(key))
+    }
+}
+
+impl Index {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, key: String, size: u64) {
+        let last_used = self.tick();
+        self.entries.insert(key, Entry { size, last_used, pins: 0 });
+        self.size += size;
+    }
+}
+
+impl<'a> CachedCompiler<'a> {
+    pub fn new(
+        compiler: &'a dyn Compiler,
+        fingerprint: &'a [u8],
+        cache: Option<&'a Cache>,
+    ) -> CachedCompiler<'a> {
+        CachedCompiler { compiler, fingerprint, cache }
+    }
+}
+
+impl<'a> Compiler for CachedCompiler<'a> {
+    fn compile(&self, source: &Source, extras: &Files, pool: &Pool<Sandbox>)
+        -> Result<Target, CompileError> {
+        let cache = match self.cache {
+            Some(cache) => cache,
+            None => return self.compiler.compile(source, extras, pool),
+        };
+        let key = cache_key(self.fingerprint, source, extras);
+        if let Some(target) = cache.get(&key) {
+            return Ok(target);
+        }
+        let target = self.compiler.compile(source, extras, pool)?;
+        // Failing to cache is not a compile failure.
+        let _ = cache.put(&key, &target);
+        Ok(target)
+    }
+}
+
+// The fingerprint identifies the language and its compiler config.
+fn cache_key(fingerprint: &[u8], source: &Source, extras: &Files) -> String {
+    let mut hasher = Sha256::default();
+    hasher.input(&(fingerprint.len() as u64).to_le_bytes());
+    hasher.input(fingerprint);
+    hasher.input(&bincode::serialize(&(source, extras)).unwrap());
+    hex::encode(hasher.result())
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for result in fs::read_dir(path)? {
+        let entry = result?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use compile::Interpreter;
+
+    struct CountingCompiler(Interpreter, AtomicUsize);
+
+    impl Compiler for CountingCompiler {
+        fn compile(&self, source: &Source, extras: &Files, pool: &Pool<Sandbox>)
+            -> Result<Target, CompileError> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.compile(source, extras, pool)
+        }
+    }
+
+    fn counting_compiler() -> CountingCompiler {
+        CountingCompiler(Interpreter::new(
+            PathBuf::from("foo.sh"),
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"), String::from("foo.sh")])),
+            AtomicUsize::new(0))
+    }
+
+    fn echo() -> Source {
+        Source::Single(b"echo 1".to_vec())
+    }
+
+    #[test]
+    fn hit_and_evict() {
+        let dir = TempDir::new("jd-cache").unwrap();
+        let pool = Pool::new();
+        let inner = counting_compiler();
+        let probe = Cache::new(dir.path().join("probe"), u64::MAX).unwrap();
+        CachedCompiler::new(&inner, b"sh", Some(&probe))
+            .compile(&echo(), &Files::new(), &pool).unwrap();
+        let entry_size = probe.size();
+        let cache = Cache::new(
+            dir.path().join("cache"), entry_size * 3 / 2).unwrap();
+        let compiler = CachedCompiler::new(&inner, b"sh", Some(&cache));
+        let other = CachedCompiler::new(&inner, b"bash", Some(&cache));
+        compiler.compile(&echo(), &Files::new(), &pool).unwrap();
+        compiler.compile(&echo(), &Files::new(), &pool).unwrap();
+        assert_eq!(inner.1.load(Ordering::SeqCst), 2);
+        other.compile(&echo(), &Files::new(), &pool).unwrap();
+        assert_eq!(inner.1.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.size(), entry_size);
+        compiler.compile(&echo(), &Files::new(), &pool).unwrap();
+        assert_eq!(inner.1.load(Ordering::SeqCst), 4);
+        let mut extras = Files::new();
+        extras.insert(PathBuf::from("lib.sh"), b"true".to_vec()).unwrap();
+        compiler.compile(&echo(), &extras, &pool).unwrap();
+        assert_eq!(inner.1.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn pinned_not_evicted() {
+        let dir = TempDir::new("jd-cache").unwrap();
+        let pool = Pool::new();
+        let inner = counting_compiler();
+        let probe = Cache::new(dir.path().join("probe"), u64::MAX).unwrap();
+        CachedCompiler::new(&inner, b"sh", Some(&probe))
+            .compile(&echo(), &Files::new(), &pool).unwrap();
+        let entry_size = probe.size();
+        let cache = Cache::new(
+            dir.path().join("cache"), entry_size * 3 / 2).unwrap();
+        let compiler = CachedCompiler::new(&inner, b"sh", Some(&cache));
+        let other = CachedCompiler::new(&inner, b"bash", Some(&cache));
+        compiler.compile(&echo(), &Files::new(), &pool).unwrap();
+        let set_pins = |pins| {
+            let mut index = cache.index.lock().unwrap();
+            index.entries.values_mut().next().unwrap().pins = pins;
+        };
+        set_pins(1);
+        other.compile(&echo(), &Files::new(), &pool).unwrap();
+        assert_eq!(cache.size(), entry_size);
+        set_pins(0);
+        compiler.compile(&echo(), &Files::new(), &pool).unwrap();
+        assert_eq!(inner.1.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn reopen() {
+        let dir = TempDir::new("jd-cache").unwrap();
+        let pool = Pool::new();
+        let inner = counting_compiler();
+        let cache = Cache::new(dir.path().to_path_buf(), 1048576).unwrap();
+        CachedCompiler::new(&inner, b"sh", Some(&cache))
+            .compile(&echo(), &Files::new(), &pool).unwrap();
+        drop(cache);
+        let cache = Cache::new(dir.path().to_path_buf(), 1048576).unwrap();
+        assert!(cache.size() > 0);
+        CachedCompiler::new(&inner, b"sh", Some(&cache))
+            .compile(&echo(), &Files::new(), &pool).unwrap();
+        assert_eq!(inner.1.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,28 +1,68 @@
 use std::collections::HashMap;
 use std::io::{self, BufRead, BufReader, Read, Seek};
 use std::num::{ParseFloatError, ParseIntError};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::slice;
 use std::time::Duration;
-use zip::read::ZipArchive;
+use serde_yaml;
+use zip::read::{ZipArchive, ZipFile};
 use zip::result::ZipError;
+use check::CheckerConfig;
+use compile::{Files, Source};
+use sandbox;
 
 pub struct CaseVec<R: Read + Seek> {
     archive: ZipArchive<R>,
+    kind: ProblemKind,
+    graders: HashMap<String, GraderConfig>,
+    interactor: Option<SourceConfig>,
+    checker: CheckerConfig,
+    special_judge: Option<SourceConfig>,
+    subtasks: Box<[Subtask]>,
+    policy: Policy,
+    stack: Option<u64>,
     config: Box<[CaseConfig]>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProblemKind {
+    // The submission is a whole program.
+    Program,
+    // The submission implements functions called by a grader, which the
+    // package provides for each supported language.
+    Function,
+    // The submission talks to an interactor provided by the package.
+    Interactive,
+}
+
+pub struct GraderConfig {
+    code_file: PathBuf,
+    file_names: Box<[String]>,
+}
+
+// A program in the package, built with the compiler of the language.
+pub struct SourceConfig {
+    language: String,
+    file_name: String,
+}
+
 pub type CaseResult<T> = Result<T, CaseError>;
 
 #[derive(Debug)]
 pub enum CaseError {
     Io(io::Error),
     InvalidArchive,
-    FileNotFound,
-    ParseError,
+    // Names the file, or what it is for.
+    FileNotFound(String),
+    ParseError(String),
 }
 
+const DEFAULT_TIME: Duration = Duration::from_secs(1);
 const DEFAULT_MEMORY: usize = 268435456;
 
-struct CaseConfig {
+pub struct CaseConfig {
     input_name: String,
     output_name: String,
     time: Duration,
@@ -30,6 +70,39 @@ struct CaseConfig {
     score: i32,
 }
 
+// A group of consecutive cases scored together. A subtask is judged only if
+// the subtasks it depends on passed, that is, all their cases are accepted.
+pub struct Subtask {
+    score: i32,
+    scoring: Scoring,
+    depends: Box<[usize]>,
+    cases: Range<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scoring {
+    // The score of the subtask if all cases are accepted, or zero otherwise.
+    // The remaining cases are skipped after the first one not accepted.
+    Min,
+    // The sum of the scores of the cases.
+    Sum,
+}
+
+// Which cases are judged, and how the problem is scored.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    // Stops at the first case not accepted. Scores only if all cases are
+    // accepted.
+    Acm,
+    // Judges every case, and sums the scores of the subtasks.
+    Oi,
+    // Like OI, but skips the cases which can no longer score.
+    #[default]
+    Ioi,
+}
+
 impl<R: Read + Seek> CaseVec<R> {
     pub fn load(package: R) -> CaseResult<CaseVec<R>> {
         let mut archive = ZipArchive::new(package)?;
@@ -39,50 +112,206 @@ impl<R: Read + Seek> CaseVec<R> {
             canonical_names.insert(file.name().to_ascii_lowercase(),
                                    file.name().to_string());
         }
-        let config = match canonical_names.get("config.ini") {
+        let problem = match canonical_names.get("config.ini") {
             Some(name) =>
-                parse_legacy_config(archive.by_name(name)?, &canonical_names)?,
+                parse_legacy_config(by_name(&mut archive, name)?,
+                                    &canonical_names)?,
             None => match canonical_names.get("config.yaml") {
-                Some(_) => panic!("not implemented"),
-                None => return Err(CaseError::FileNotFound),
+                Some(name) =>
+                    parse_yaml_config(by_name(&mut archive, name)?,
+                                      &canonical_names)?,
+                None => return Err(CaseError::FileNotFound(
+                    String::from("config.ini or config.yaml"))),
             },
         };
-        Ok(CaseVec { archive, config })
+        Ok(CaseVec {
+            archive,
+            kind: problem.kind,
+            graders: problem.graders,
+            interactor: problem.interactor,
+            checker: problem.checker,
+            special_judge: problem.special_judge,
+            subtasks: problem.subtasks,
+            policy: problem.policy,
+            stack: problem.stack,
+            config: problem.cases,
+        })
+    }
+
+    pub fn kind(&self) -> ProblemKind {
+        self.kind
+    }
+
+    pub fn grader(&self, language: &str) -> Option<&GraderConfig> {
+        self.graders.get(language)
+    }
+
+    // Grader files are placed by their file names.
+    pub fn read_grader_files(&mut self, language: &str) -> CaseResult<Files> {
+        let grader = self.graders.get(language).ok_or_else(|| {
+            CaseError::FileNotFound(format!("grader for {}", language))
+        })?;
+        let mut files = Files::new();
+        for name in grader.file_names.iter() {
+            let mut data = Vec::new();
+            by_name(&mut self.archive, name)?.read_to_end(&mut data)?;
+            let file_name = Path::new(name).file_name()
+                .ok_or_else(|| CaseError::FileNotFound(name.clone()))?;
+            files.insert(PathBuf::from(file_name), data).map_err(|_| {
+                CaseError::ParseError(format!("invalid grader file: {}", name))
+            })?;
+        }
+        Ok(files)
+    }
+
+    pub fn checker(&self) -> CheckerConfig {
+        self.checker
+    }
+
+    pub fn interactor(&self) -> Option<&SourceConfig> {
+        self.interactor.as_ref()
+    }
+
+    pub fn read_interactor(&mut self) -> CaseResult<Source> {
+        let name = match self.interactor {
+            Some(ref interactor) => interactor.file_name.clone(),
+            None => return Err(
+                CaseError::FileNotFound(String::from("interactor"))),
+        };
+        self.read_source(&name)
+    }
+
+    // When present, checks the output in place of the checker.
+    pub fn special_judge(&self) -> Option<&SourceConfig> {
+        self.special_judge.as_ref()
+    }
+
+    pub fn read_special_judge(&mut self) -> CaseResult<Source> {
+        let name = match self.special_judge {
+            Some(ref special_judge) => special_judge.file_name.clone(),
+            None => return Err(
+                CaseError::FileNotFound(String::from("special judge"))),
+        };
+        self.read_source(&name)
+    }
+
+    fn read_source(&mut self, name: &str) -> CaseResult<Source> {
+        let mut data = Vec::new();
+        by_name(&mut self.archive, name)?.read_to_end(&mut data)?;
+        Ok(Source::Single(data))
+    }
+
+    // Problems without subtasks have all cases in a single subtask summing
+    // their scores.
+    pub fn subtasks(&self) -> &[Subtask] {
+        &self.subtasks
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    // Bytes of stack for the submission, or sandbox::UNLIMITED. None keeps the
+    // limit of the sandbox.
+    pub fn stack(&self) -> Option<u64> {
+        self.stack
     }
 
     pub fn len(&self) -> usize {
         self.config.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.config.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&CaseConfig> {
+        self.config.get(index)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, CaseConfig> {
+        self.config.iter()
+    }
+
+    pub fn open_input(&mut self, index: usize) -> CaseResult<ZipFile<'_>> {
+        let case = self.config.get(index).ok_or_else(|| {
+            CaseError::FileNotFound(format!("input of case {}", index))
+        })?;
+        by_name(&mut self.archive, &case.input_name)
+    }
+
+    pub fn open_output(&mut self, index: usize) -> CaseResult<ZipFile<'_>> {
+        let case = self.config.get(index).ok_or_else(|| {
+            CaseError::FileNotFound(format!("output of case {}", index))
+        })?;
+        by_name(&mut self.archive, &case.output_name)
+    }
 }
 
+impl<'a, R: Read + Seek> IntoIterator for &'a CaseVec<R> {
+    type Item = &'a CaseConfig;
+    type IntoIter = slice::Iter<'a, CaseConfig>;
+
+    fn into_iter(self) -> slice::Iter<'a, CaseConfig> {
+        self.iter()
+    }
+}
+
+impl CaseConfig {
+    pub fn input_name(&self) -> &str { &self.input_name }
+    pub fn output_name(&self) -> &str { &self.output_name }
+    pub fn time(&self) -> Duration { self.time }
+    pub fn memory(&self) -> usize { self.memory }
+    pub fn score(&self) -> i32 { self.score }
+}
+
+impl Subtask {
+    pub fn score(&self) -> i32 { self.score }
+    pub fn scoring(&self) -> Scoring { self.scoring }
+    pub fn depends(&self) -> &[usize] { &self.depends }
+    pub fn cases(&self) -> Range<usize> { self.cases.clone() }
+}
+
+impl GraderConfig {
+    pub fn code_file(&self) -> &Path { &self.code_file }
+}
+
+impl SourceConfig {
+    pub fn language(&self) -> &str { &self.language }
+}
+
+// The cases may be followed by "checker=" and "policy=" lines, whose values
+// are as written in config.yaml.
 fn parse_legacy_config<R: Read>(
     config: R,
     canonical_names: &HashMap<String, String>,
-) -> CaseResult<Box<[CaseConfig]>> {
+) -> CaseResult<ProblemConfig> {
     let mut lines = BufReader::new(config).lines();
-    let num_cases = lines.next().ok_or(CaseError::ParseError)??.parse()?;
+    let num_cases = lines.next().ok_or_else(unexpected_end)??.parse()?;
     let mut configs = Vec::with_capacity(num_cases);
     for _ in 0..num_cases {
-        let line = lines.next().ok_or(CaseError::ParseError)??;
+        let line = lines.next().ok_or_else(unexpected_end)??;
         let mut parts = line.split('|');
         let input_lowercase = format!(
             "input/{}",
-            parts.next().ok_or(CaseError::ParseError)?.to_ascii_lowercase());
+            parts.next().ok_or_else(unexpected_end)?.to_ascii_lowercase());
         let input_name = canonical_names.get(&input_lowercase)
-            .ok_or(CaseError::FileNotFound)?.to_string();
+            .ok_or_else(|| CaseError::FileNotFound(input_lowercase.clone()))?
+            .to_string();
         let output_lowercase = format!(
             "output/{}",
-            parts.next().ok_or(CaseError::ParseError)?.to_ascii_lowercase());
+            parts.next().ok_or_else(unexpected_end)?.to_ascii_lowercase());
         let output_name = canonical_names.get(&output_lowercase)
-            .ok_or(CaseError::FileNotFound)?.to_string();
+            .ok_or_else(|| CaseError::FileNotFound(output_lowercase.clone()))?
+            .to_string();
         let time_sec: f64 =
-            parts.next().ok_or(CaseError::ParseError)?.parse()?;
+            parts.next().ok_or_else(unexpected_end)?.parse()?;
         let time_nanos: u64 = (time_sec * 1e9) as u64;
         let time = Duration::new(time_nanos / 1_000_000_000,
                                  (time_nanos % 1_000_000_000) as u32);
-        let score: i32 = parts.next().ok_or(CaseError::ParseError)?.parse()?;
+        let score: i32 = parts.next().ok_or_else(unexpected_end)?.parse()?;
         let memory =
-            match parts.next().ok_or(CaseError::ParseError)?.parse::<f64>() {
+            match parts.next().ok_or_else(unexpected_end)?.parse::<f64>() {
                 Ok(memory_kb) => (memory_kb * 1024.) as usize,
                 Err(_) => DEFAULT_MEMORY,
             };
@@ -90,7 +319,392 @@ fn parse_legacy_config<R: Read>(
             CaseConfig { input_name, output_name, time, memory, score };
         configs.push(config)
     }
-    Ok(configs.into_boxed_slice())
+    let mut checker = CheckerConfig::default();
+    let mut policy = Policy::default();
+    for line in lines {
+        let line = line?;
+        let line = line.trim();
+        if let Some(value) = line.strip_prefix("checker=") {
+            checker = serde_yaml::from_str(value).map_err(|e| {
+                CaseError::ParseError(format!("config.ini: checker: {}", e))
+            })?;
+        } else if let Some(value) = line.strip_prefix("policy=") {
+            policy = serde_yaml::from_str(value).map_err(|e| {
+                CaseError::ParseError(format!("config.ini: policy: {}", e))
+            })?;
+        }
+    }
+    Ok(ProblemConfig {
+        kind: ProblemKind::Program,
+        graders: HashMap::new(),
+        interactor: None,
+        checker,
+        special_judge: None,
+        subtasks: whole_subtask(&configs),
+        policy,
+        stack: None,
+        cases: configs.into_boxed_slice(),
+    })
+}
+
+struct ProblemConfig {
+    kind: ProblemKind,
+    graders: HashMap<String, GraderConfig>,
+    interactor: Option<SourceConfig>,
+    checker: CheckerConfig,
+    special_judge: Option<SourceConfig>,
+    subtasks: Box<[Subtask]>,
+    policy: Policy,
+    stack: Option<u64>,
+    cases: Box<[CaseConfig]>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlConfig {
+    #[serde(rename = "type")]
+    kind: Option<ProblemKind>,
+    #[serde(default)]
+    graders: HashMap<String, YamlGrader>,
+    interactor: Option<YamlSource>,
+    checker: Option<CheckerConfig>,
+    special_judge: Option<YamlSource>,
+    #[serde(default)]
+    policy: Policy,
+    time: Option<Quantity>,
+    memory: Option<Quantity>,
+    stack: Option<Quantity>,
+    score: Option<i32>,
+    cases: Option<Vec<YamlCase>>,
+    subtasks: Option<Vec<YamlSubtask>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlSubtask {
+    score: Option<i32>,
+    scoring: Option<Scoring>,
+    #[serde(default)]
+    depends: Vec<usize>,
+    cases: Vec<YamlCase>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlGrader {
+    code_file: PathBuf,
+    files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlSource {
+    language: String,
+    file: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct YamlCase {
+    input: String,
+    output: String,
+    time: Option<Quantity>,
+    memory: Option<Quantity>,
+    score: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum Quantity {
+    Number(f64),
+    Text(String),
+}
+
+fn parse_yaml_config<R: Read>(
+    config: R,
+    canonical_names: &HashMap<String, String>,
+) -> CaseResult<ProblemConfig> {
+    let config: YamlConfig = serde_yaml::from_reader(config)
+        .map_err(|e| CaseError::ParseError(format!("config.yaml: {}", e)))?;
+    let kind = config.kind.unwrap_or(ProblemKind::Program);
+    if (kind == ProblemKind::Function) == config.graders.is_empty() {
+        return Err(CaseError::ParseError(String::from(
+            "config.yaml: graders are required by and only by function problems")));
+    }
+    if (kind == ProblemKind::Interactive) != config.interactor.is_some() {
+        return Err(CaseError::ParseError(String::from(
+            "config.yaml: interactor is required by and only by interactive problems")));
+    }
+    if config.checker.is_some() && config.special_judge.is_some() {
+        return Err(CaseError::ParseError(String::from(
+            "config.yaml: checker and special_judge are exclusive")));
+    }
+    let interactor = match config.interactor {
+        Some(ref interactor) =>
+            Some(parse_source(interactor, canonical_names)?),
+        None => None,
+    };
+    let special_judge = match config.special_judge {
+        Some(ref special_judge) =>
+            Some(parse_source(special_judge, canonical_names)?),
+        None => None,
+    };
+    let mut graders = HashMap::with_capacity(config.graders.len());
+    for (language, grader) in config.graders {
+        let file_names = grader.files.iter().map(|name| {
+            canonical_names.get(&name.to_ascii_lowercase()).cloned()
+                .ok_or_else(|| CaseError::FileNotFound(name.clone()))
+        }).collect::<CaseResult<Vec<_>>>()?;
+        // Grader files are placed by their file names, next to the code file.
+        let mut placed = vec![grader.code_file.as_path()];
+        for name in file_names.iter() {
+            let placed_name = Path::new(name).file_name().map(Path::new);
+            match placed_name {
+                Some(placed_name) if !placed.contains(&placed_name) =>
+                    placed.push(placed_name),
+                _ => return Err(CaseError::ParseError(format!(
+                    "config.yaml: grader file {} of {} is placed twice",
+                    name, language))),
+            }
+        }
+        graders.insert(language, GraderConfig {
+            code_file: grader.code_file,
+            file_names: file_names.into_boxed_slice(),
+        });
+    }
+    let default_time = match config.time {
+        Some(ref time) => parse_time(time)?,
+        None => DEFAULT_TIME,
+    };
+    let default_memory = match config.memory {
+        Some(ref memory) => parse_memory(memory)?,
+        None => DEFAULT_MEMORY,
+    };
+    let stack = match config.stack {
+        Some(ref stack) => Some(parse_stack(stack)?),
+        None => None,
+    };
+    let defaults = CaseDefaults {
+        time: default_time,
+        memory: default_memory,
+        score: config.score,
+    };
+    let mut configs = Vec::new();
+    let subtasks = match (config.cases, config.subtasks) {
+        (Some(cases), None) => {
+            for case in cases.iter() {
+                configs.push(
+                    parse_yaml_case(case, &defaults, configs.len(),
+                                    canonical_names)?);
+            }
+            whole_subtask(&configs)
+        },
+        (None, Some(subtasks)) => {
+            let mut results = Vec::with_capacity(subtasks.len());
+            for (index, subtask) in subtasks.iter().enumerate() {
+                results.push(parse_yaml_subtask(
+                    subtask, index, &defaults, &mut configs, canonical_names)?);
+            }
+            results.into_boxed_slice()
+        },
+        _ => return Err(CaseError::ParseError(String::from(
+            "config.yaml: exactly one of cases and subtasks is required"))),
+    };
+    Ok(ProblemConfig {
+        kind,
+        graders,
+        interactor,
+        checker: config.checker.unwrap_or_default(),
+        special_judge,
+        subtasks,
+        policy: config.policy,
+        stack,
+        cases: configs.into_boxed_slice(),
+    })
+}
+
+struct CaseDefaults {
+    time: Duration,
+    memory: usize,
+    score: Option<i32>,
+}
+
+fn parse_yaml_case(
+    case: &YamlCase,
+    defaults: &CaseDefaults,
+    index: usize,
+    canonical_names: &HashMap<String, String>,
+) -> CaseResult<CaseConfig> {
+    let input_name = canonical_names.get(&case.input.to_ascii_lowercase())
+        .ok_or_else(|| CaseError::FileNotFound(case.input.clone()))?
+        .to_string();
+    let output_name = canonical_names.get(&case.output.to_ascii_lowercase())
+        .ok_or_else(|| CaseError::FileNotFound(case.output.clone()))?
+        .to_string();
+    let time = match case.time {
+        Some(ref time) => parse_time(time)?,
+        None => defaults.time,
+    };
+    let memory = match case.memory {
+        Some(ref memory) => parse_memory(memory)?,
+        None => defaults.memory,
+    };
+    let score = case.score.or(defaults.score).ok_or_else(|| {
+        CaseError::ParseError(format!("case {}: missing score", index))
+    })?;
+    Ok(CaseConfig { input_name, output_name, time, memory, score })
+}
+
+// Cases of min subtasks score nothing by themselves, and sum subtasks score
+// the sum of their cases.
+fn parse_yaml_subtask(
+    subtask: &YamlSubtask,
+    index: usize,
+    defaults: &CaseDefaults,
+    configs: &mut Vec<CaseConfig>,
+    canonical_names: &HashMap<String, String>,
+) -> CaseResult<Subtask> {
+    let error = |message: &str| {
+        CaseError::ParseError(format!("subtask {}: {}", index, message))
+    };
+    if subtask.depends.iter().any(|&depend| depend >= index) {
+        return Err(error("may only depend on earlier subtasks"));
+    }
+    if subtask.cases.is_empty() {
+        return Err(error("no cases"));
+    }
+    let scoring = subtask.scoring.unwrap_or(Scoring::Min);
+    let start = configs.len();
+    for case in subtask.cases.iter() {
+        let config = match scoring {
+            Scoring::Min => {
+                if case.score.is_some() {
+                    return Err(error("cases of min subtasks have no score"));
+                }
+                let defaults = CaseDefaults { score: Some(0), ..*defaults };
+                parse_yaml_case(case, &defaults, configs.len(),
+                                canonical_names)?
+            },
+            Scoring::Sum => parse_yaml_case(
+                case, defaults, configs.len(), canonical_names)?,
+        };
+        configs.push(config);
+    }
+    let cases = start..configs.len();
+    let score = match (scoring, subtask.score) {
+        (Scoring::Min, Some(score)) => score,
+        (Scoring::Min, None) => return Err(error("missing score")),
+        (Scoring::Sum, None) => configs[cases.clone()].iter()
+            .map(CaseConfig::score).sum(),
+        (Scoring::Sum, Some(_)) =>
+            return Err(error("sum subtasks are scored by their cases")),
+    };
+    Ok(Subtask {
+        score,
+        scoring,
+        depends: subtask.depends.clone().into_boxed_slice(),
+        cases,
+    })
+}
+
+fn whole_subtask(configs: &[CaseConfig]) -> Box<[Subtask]> {
+    Box::new([Subtask {
+        score: configs.iter().map(CaseConfig::score).sum(),
+        scoring: Scoring::Sum,
+        depends: Box::new([]),
+        cases: 0..configs.len(),
+    }])
+}
+
+fn parse_source(
+    source: &YamlSource,
+    canonical_names: &HashMap<String, String>,
+) -> CaseResult<SourceConfig> {
+    Ok(SourceConfig {
+        language: source.language.clone(),
+        file_name: canonical_names.get(&source.file.to_ascii_lowercase())
+            .ok_or_else(|| CaseError::FileNotFound(source.file.clone()))?
+            .to_string(),
+    })
+}
+
+// Accepts seconds as a plain number, or a number suffixed by "s", "ms" or
+// "us".
+pub(crate) fn parse_time(time: &Quantity) -> CaseResult<Duration> {
+    let time_sec = match *time {
+        Quantity::Number(sec) => sec,
+        Quantity::Text(ref text) => {
+            let text = text.trim();
+            let (number, scale) = if let Some(number) = text.strip_suffix("ms") {
+                (number, 1e-3)
+            } else if let Some(number) = text.strip_suffix("us") {
+                (number, 1e-6)
+            } else if let Some(number) = text.strip_suffix('s') {
+                (number, 1.)
+            } else {
+                (text, 1.)
+            };
+            number.trim().parse::<f64>().map_err(|_| {
+                CaseError::ParseError(format!("invalid time: {}", text))
+            })? * scale
+        },
+    };
+    if !time_sec.is_finite() || time_sec <= 0. {
+        return Err(CaseError::ParseError(
+            format!("invalid time: {}", time_sec)));
+    }
+    let time_nanos: u64 = (time_sec * 1e9) as u64;
+    Ok(Duration::new(time_nanos / 1_000_000_000,
+                     (time_nanos % 1_000_000_000) as u32))
+}
+
+// Accepts bytes as a plain number, or a number suffixed by "k", "m" or "g"
+// (case-insensitive, with an optional trailing "b").
+pub(crate) fn parse_memory(memory: &Quantity) -> CaseResult<usize> {
+    let memory_bytes = match *memory {
+        Quantity::Number(bytes) => bytes,
+        Quantity::Text(ref text) => {
+            let lower = text.trim().to_ascii_lowercase();
+            let lower = lower.strip_suffix('b').unwrap_or(&lower);
+            let (number, scale) = match lower.chars().last() {
+                Some('k') => (&lower[..lower.len() - 1], 1024.),
+                Some('m') => (&lower[..lower.len() - 1], 1048576.),
+                Some('g') => (&lower[..lower.len() - 1], 1073741824.),
+                _ => (lower, 1.),
+            };
+            number.trim().parse::<f64>().map_err(|_| {
+                CaseError::ParseError(format!("invalid memory: {}", text))
+            })? * scale
+        },
+    };
+    if !memory_bytes.is_finite() || memory_bytes <= 0. {
+        return Err(CaseError::ParseError(
+            format!("invalid memory: {}", memory_bytes)));
+    }
+    Ok(memory_bytes as usize)
+}
+
+// Accepts what parse_memory does, or "unlimited".
+fn parse_stack(stack: &Quantity) -> CaseResult<u64> {
+    match *stack {
+        Quantity::Text(ref text) if text.trim() == "unlimited" =>
+            Ok(sandbox::UNLIMITED),
+        _ => Ok(parse_memory(stack)? as u64),
+    }
+}
+
+fn by_name<'a, R: Read + Seek>(
+    archive: &'a mut ZipArchive<R>,
+    name: &str,
+) -> CaseResult<ZipFile<'a>> {
+    archive.by_name(name).map_err(|e| match e {
+        ZipError::FileNotFound => CaseError::FileNotFound(name.to_string()),
+        e => CaseError::from(e),
+    })
+}
+
+fn unexpected_end() -> CaseError {
+    CaseError::ParseError(String::from("config.ini: unexpected end of line"))
 }
 
 impl From<io::Error> for CaseError {
@@ -105,20 +719,21 @@ impl From<ZipError> for CaseError {
             ZipError::Io(e) => CaseError::Io(e),
             ZipError::InvalidArchive(_) | ZipError::UnsupportedArchive(_) =>
                 CaseError::InvalidArchive,
-            ZipError::FileNotFound => CaseError::FileNotFound,
+            // Files are looked up through by_name, which knows the name.
+            ZipError::FileNotFound => CaseError::FileNotFound(String::new()),
         }
     }
 }
 
 impl From<ParseFloatError> for CaseError {
-    fn from(_: ParseFloatError) -> CaseError {
-        CaseError::ParseError
+    fn from(e: ParseFloatError) -> CaseError {
+        CaseError::ParseError(format!("config.ini: {}", e))
     }
 }
 
 impl From<ParseIntError> for CaseError {
-    fn from(_: ParseIntError) -> CaseError {
-        CaseError::ParseError
+    fn from(e: ParseIntError) -> CaseError {
+        CaseError::ParseError(format!("config.ini: {}", e))
     }
 }
 
@@ -130,10 +745,19 @@ mod tests {
     #[test]
     fn invalid_archive() {
         let reader = Cursor::new(&[]);
-        assert!(match CaseVec::load(reader) {
-            Err(CaseError::InvalidArchive) => true,
-            _ => false,
-        });
+        assert!(matches!(CaseVec::load(reader),
+                         Err(CaseError::InvalidArchive)));
+    }
+
+    #[test]
+    fn missing_file() {
+        let canonical_names = HashMap::new();
+        let config = b"1\na.in|a.out|1|100|65536\n";
+        match parse_legacy_config(&config[..], &canonical_names) {
+            Err(CaseError::FileNotFound(name)) =>
+                assert_eq!(name, "input/a.in"),
+            _ => panic!("file found"),
+        }
     }
 
     #[test]
@@ -142,5 +766,178 @@ mod tests {
         let reader = Cursor::new(&data[..]);
         let mut cases = CaseVec::load(reader).unwrap();
         assert_eq!(cases.len(), 10);
+        assert_eq!(cases.iter().map(CaseConfig::score).sum::<i32>(), 100);
+        let case = cases.get(3).unwrap();
+        assert_eq!(case.time(), Duration::from_secs(1));
+        assert_eq!(case.memory(), 16777216);
+        let mut input = String::new();
+        cases.open_input(0).unwrap().read_to_string(&mut input).unwrap();
+        assert_eq!(input, "18820 26832\r\n");
+        let mut output = String::new();
+        cases.open_output(0).unwrap().read_to_string(&mut output).unwrap();
+        assert_eq!(output, "45652\r\n");
+        assert!(matches!(cases.open_input(10),
+                         Err(CaseError::FileNotFound(_))));
+        assert!(matches!(cases.open_output(10),
+                         Err(CaseError::FileNotFound(_))));
+    }
+
+    #[test]
+    fn yaml_archive() {
+        let data = include_bytes!("testdata/aplusb.zip");
+        let reader = Cursor::new(&data[..]);
+        let cases = CaseVec::load(reader).unwrap();
+        assert_eq!(cases.len(), 10);
+        assert_eq!(cases.config[0].input_name, "data/0.in");
+        assert_eq!(cases.config[0].time, Duration::from_secs(1));
+        assert_eq!(cases.config[0].memory, 67108864);
+        assert_eq!(cases.config[0].score, 10);
+        assert_eq!(cases.config[8].time, Duration::from_millis(500));
+        assert_eq!(cases.config[9].time, Duration::from_secs(2));
+        assert_eq!(cases.config[9].memory, 134217728);
+        assert_eq!(cases.config[9].score, 20);
+    }
+
+    #[test]
+    fn function_archive() {
+        let data = include_bytes!("testdata/aplusb-function.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        assert_eq!(cases.kind(), ProblemKind::Function);
+        assert_eq!(cases.grader("c").unwrap().code_file(), Path::new("add.c"));
+        assert!(cases.grader("py").is_none());
+        let files = cases.read_grader_files("c").unwrap();
+        assert_eq!(files.paths().collect::<Vec<_>>(),
+                   [Path::new("add.h"), Path::new("grader.c")]);
+    }
+
+    #[test]
+    fn grader_files_placed_twice() {
+        let mut canonical_names = HashMap::new();
+        for name in &["a.in", "a.out", "c/grader.h", "common/grader.h",
+                      "c/add.c"] {
+            canonical_names.insert(name.to_string(), name.to_string());
+        }
+        let parse = |files: &str| {
+            parse_yaml_config(format!("\
+                type: function\n\
+                graders: {{c: {{code_file: add.c, files: [{}]}}}}\n\
+                cases: [{{input: a.in, output: a.out, score: 100}}]\n",
+                files).as_bytes(), &canonical_names)
+        };
+        assert!(parse("c/grader.h").is_ok());
+        assert!(matches!(parse("c/grader.h, common/grader.h"),
+                         Err(CaseError::ParseError(_))));
+        assert!(matches!(parse("c/add.c"), Err(CaseError::ParseError(_))));
+    }
+
+    #[test]
+    fn interactive_archive() {
+        let data = include_bytes!("testdata/guess.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        assert_eq!(cases.kind(), ProblemKind::Interactive);
+        assert_eq!(cases.interactor().unwrap().language(), "c");
+        match cases.read_interactor().unwrap() {
+            Source::Single(code) => assert!(code.starts_with(b"#include")),
+            Source::Files(_) => panic!("multiple files"),
+        }
+    }
+
+    #[test]
+    fn checker_config() {
+        let mut canonical_names = HashMap::new();
+        canonical_names.insert(String::from("a.in"), String::from("a.in"));
+        canonical_names.insert(String::from("a.out"), String::from("a.out"));
+        canonical_names.insert(String::from("input/a.in"),
+                               String::from("input/a.in"));
+        canonical_names.insert(String::from("output/a.out"),
+                               String::from("output/a.out"));
+        let config = b"checker: {float: {relative: 0.001}}\ncases:\n  \
+                       - {input: a.in, output: a.out, score: 100}\n";
+        assert_eq!(parse_yaml_config(&config[..], &canonical_names)
+                       .unwrap().checker,
+                   CheckerConfig::Float { absolute: 1e-6, relative: 0.001 });
+        let config = b"cases:\n  - {input: a.in, output: a.out, score: 100}\n";
+        assert_eq!(parse_yaml_config(&config[..], &canonical_names)
+                       .unwrap().checker,
+                   CheckerConfig::Lines);
+        let config =
+            b"1\na.in|a.out|1|100|65536\nchecker=tokens\npolicy=acm\n";
+        let problem = parse_legacy_config(&config[..], &canonical_names)
+            .unwrap();
+        assert_eq!(problem.checker, CheckerConfig::Tokens);
+        assert_eq!(problem.policy, Policy::Acm);
+        let config = b"1\na.in|a.out|1|100|65536\nchecker=fuzzy\n";
+        assert!(matches!(parse_legacy_config(&config[..], &canonical_names),
+                         Err(CaseError::ParseError(_))));
+    }
+
+    #[test]
+    fn subtasks() {
+        let data = include_bytes!("testdata/aplusb-subtasks.zip");
+        let cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        assert_eq!(cases.len(), 10);
+        let subtasks = cases.subtasks();
+        assert_eq!(subtasks.len(), 4);
+        assert_eq!(subtasks[0].scoring(), Scoring::Min);
+        assert_eq!(subtasks[0].cases(), 0..3);
+        assert_eq!(subtasks[1].depends(), [0]);
+        assert_eq!(subtasks[2].scoring(), Scoring::Sum);
+        assert_eq!(subtasks[2].score(), 30);
+        assert_eq!(subtasks[3].cases(), 9..10);
+        assert_eq!(cases.get(0).unwrap().score(), 0);
+        assert_eq!(cases.policy(), Policy::Ioi);
+        let data = include_bytes!("testdata/aplusb.zip");
+        let cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        assert_eq!(cases.subtasks().len(), 1);
+        assert_eq!(cases.subtasks()[0].score(), 110);
+        assert_eq!(cases.subtasks()[0].cases(), 0..10);
+    }
+
+    #[test]
+    fn invalid_subtasks() {
+        let mut canonical_names = HashMap::new();
+        canonical_names.insert(String::from("a.in"), String::from("a.in"));
+        canonical_names.insert(String::from("a.out"), String::from("a.out"));
+        let parse = |config: &str| {
+            parse_yaml_config(config.as_bytes(), &canonical_names)
+        };
+        let case = "cases: [{input: a.in, output: a.out}]";
+        assert!(parse(&format!("subtasks: [{{score: 1, {}}}]", case)).is_ok());
+        assert!(parse(&format!("subtasks: [{{{}}}]", case)).is_err());
+        assert!(parse(&format!(
+            "subtasks: [{{score: 1, depends: [0], {}}}]", case)).is_err());
+        assert!(parse(&format!(
+            "subtasks: [{{scoring: sum, score: 1, {}}}]", case)).is_err());
+        assert!(parse(&format!(
+            "score: 1\n{}\nsubtasks: [{{score: 1, {}}}]", case, case))
+            .is_err());
+        assert!(parse("subtasks: [{score: 1, cases: []}]").is_err());
+        assert!(parse("subtasks: [{scoring: sum, cases: []}]").is_err());
+    }
+
+    #[test]
+    fn yaml_missing_score() {
+        let config = b"cases:\n  - input: a.in\n    output: a.out\n";
+        let mut canonical_names = HashMap::new();
+        canonical_names.insert(String::from("a.in"), String::from("a.in"));
+        canonical_names.insert(String::from("a.out"), String::from("a.out"));
+        assert!(matches!(parse_yaml_config(&config[..], &canonical_names),
+                         Err(CaseError::ParseError(_))));
+    }
+
+    #[test]
+    fn quantities() {
+        let text = |s: &str| Quantity::Text(String::from(s));
+        assert_eq!(parse_time(&Quantity::Number(1.5)).unwrap(),
+                   Duration::from_millis(1500));
+        assert_eq!(parse_time(&text("250ms")).unwrap(),
+                   Duration::from_millis(250));
+        assert!(parse_time(&text("fast")).is_err());
+        assert_eq!(parse_memory(&text("16k")).unwrap(), 16384);
+        assert_eq!(parse_memory(&text("256MB")).unwrap(), 268435456);
+        assert!(parse_memory(&Quantity::Number(-1.)).is_err());
+        assert_eq!(parse_stack(&text("unlimited")).unwrap(),
+                   sandbox::UNLIMITED);
+        assert_eq!(parse_stack(&text("64m")).unwrap(), 67108864);
     }
 }
@@ -1,60 +1,309 @@
 use std::io::{self, Read, Write};
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
 use std::path::{Path, PathBuf};
-use std::sync::{Once, ONCE_INIT};
+use std::thread;
+use std::time::{Duration, Instant};
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
 use rand::{self, Rng};
 
-const CPUACCT_ROOT: &str = "/sys/fs/cgroup/cpuacct/sandbox";
-const MEMORY_ROOT: &str = "/sys/fs/cgroup/memory/sandbox";
-const PIDS_ROOT: &str = "/sys/fs/cgroup/pids/sandbox";
-const CGROUP_ROOTS: [&str; 3] = [CPUACCT_ROOT, MEMORY_ROOT, PIDS_ROOT];
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
 const CGROUP_NAME_LEN: usize = 16;
+const V2_CONTROLLERS: &str = "+cpu +memory +pids";
+const KILL_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
 
-pub struct CGroup {
-    cpuacct_dir: CGroupDir,
-    memory_dir: CGroupDir,
-    pids_dir: CGroupDir,
+pub struct CGroup(Backend);
+
+// Where the cgroups of sandboxes are created. On v1 there is one directory
+// per controller hierarchy, on v2 a single directory in the unified
+// hierarchy, which may be a subtree delegated to us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Root {
+    V1 { cpuacct: PathBuf, memory: PathBuf, pids: PathBuf, freezer: PathBuf },
+    V2(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub memory: Option<u64>,
+    pub swap: Option<u64>,
+    pub pids: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub cpu_time: Duration,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    pub memory_peak: u64,
+    pub memory_failcnt: u64,
+    pub oom_kills: u64,
+    pub pids: u64,
+}
+
+enum Backend {
+    V1 {
+        cpuacct_dir: CGroupDir,
+        memory_dir: CGroupDir,
+        pids_dir: CGroupDir,
+        freezer_dir: CGroupDir,
+    },
+    V2(CGroupDir),
 }
 
 struct CGroupDir(PathBuf);
 
 impl CGroup {
-    pub fn new() -> CGroup {
-        static INIT_CGROUP: Once = ONCE_INIT;
-        INIT_CGROUP.call_once(|| {
-            for &root in &CGROUP_ROOTS {
-                let path = Path::new(root);
-                if !path.is_dir() {
-                    fs::create_dir_all(path).unwrap();
-                }
-            }
-        });
-        let cpuacct_dir = CGroupDir::new_in(Path::new(CPUACCT_ROOT)).unwrap();
-        let memory_dir = CGroupDir::new_in(Path::new(MEMORY_ROOT)).unwrap();
-        let pids_dir = CGroupDir::new_in(Path::new(PIDS_ROOT)).unwrap();
-        CGroup { cpuacct_dir, memory_dir, pids_dir }
+    // Creates a cgroup with the limits, or returns None on hosts without
+    // cgroup delegation, where callers fall back to resource limits. Only
+    // failing to prepare the root counts as that, other failures are errors.
+    pub fn with_limits(root: &Root, limits: &Limits)
+        -> io::Result<Option<CGroup>> {
+        match root.prepare() {
+            Err(ref e) if is_undelegated(e) => return Ok(None),
+            result => result?,
+        }
+        let mut cgroup = CGroup::create_in(root)?;
+        cgroup.set_limits(limits)?;
+        Ok(Some(cgroup))
+    }
+
+    pub fn new_in(root: &Root) -> io::Result<CGroup> {
+        root.prepare()?;
+        CGroup::create_in(root)
+    }
+
+    fn create_in(root: &Root) -> io::Result<CGroup> {
+        Ok(CGroup(match *root {
+            Root::V1 { ref cpuacct, ref memory, ref pids, ref freezer } =>
+                Backend::V1 {
+                    cpuacct_dir: CGroupDir::new_in(cpuacct)?,
+                    memory_dir: CGroupDir::new_in(memory)?,
+                    pids_dir: CGroupDir::new_in(pids)?,
+                    freezer_dir: CGroupDir::new_in(freezer)?,
+                },
+            Root::V2(ref path) => Backend::V2(CGroupDir::new_in(path)?),
+        }))
     }
 
     pub fn add_task(&mut self, pid: Pid) -> io::Result<()> {
-        self.cpuacct_dir.write("tasks", &format!("{}", pid))?;
-        self.memory_dir.write("tasks", &format!("{}", pid))?;
-        self.pids_dir.write("tasks", &format!("{}", pid))?;
+        match self.0 {
+            Backend::V1 {
+                ref mut cpuacct_dir,
+                ref mut memory_dir,
+                ref mut pids_dir,
+                ref mut freezer_dir,
+            } => {
+                cpuacct_dir.write("tasks", &format!("{}", pid))?;
+                memory_dir.write("tasks", &format!("{}", pid))?;
+                pids_dir.write("tasks", &format!("{}", pid))?;
+                freezer_dir.write("tasks", &format!("{}", pid))?;
+            },
+            Backend::V2(ref mut dir) =>
+                dir.write("cgroup.procs", &format!("{}", pid))?,
+        }
+        Ok(())
+    }
+
+    pub fn set_limits(&mut self, limits: &Limits) -> io::Result<()> {
+        let memory = limits.memory.map(|memory| memory.to_string());
+        match self.0 {
+            Backend::V1 { ref mut memory_dir, ref mut pids_dir, .. } => {
+                // The memory+swap limit may never be lower than the memory
+                // limit, so lift it before touching the memory limit.
+                memory_dir.write_optional("memory.memsw.limit_in_bytes", "-1")?;
+                memory_dir.write("memory.limit_in_bytes",
+                                 memory.as_ref().map_or("-1", String::as_str))?;
+                if let Some(memory) = limits.memory {
+                    let memsw = memory + limits.swap.unwrap_or(0);
+                    memory_dir.write_optional(
+                        "memory.memsw.limit_in_bytes", &memsw.to_string())?;
+                }
+                pids_dir.write("pids.max", &max_or(limits.pids))?;
+            },
+            Backend::V2(ref mut dir) => {
+                dir.write("memory.max",
+                          memory.as_ref().map_or("max", String::as_str))?;
+                let swap = match limits.memory {
+                    Some(_) => limits.swap.unwrap_or(0).to_string(),
+                    None => max_or(limits.swap),
+                };
+                dir.write_optional("memory.swap.max", &swap)?;
+                dir.write("pids.max", &max_or(limits.pids))?;
+            },
+        }
         Ok(())
     }
 
+    pub fn usage(&self) -> io::Result<Usage> {
+        match self.0 {
+            Backend::V1 {
+                cpuacct_dir: ref cpuacct,
+                memory_dir: ref memory,
+                ref pids_dir,
+                ..
+            } => Ok(Usage {
+                cpu_time: nanos_to_duration(cpuacct.read_u64("cpuacct.usage")?),
+                user_time:
+                    nanos_to_duration(cpuacct.read_u64("cpuacct.usage_user")?),
+                system_time:
+                    nanos_to_duration(cpuacct.read_u64("cpuacct.usage_sys")?),
+                memory_peak: memory.read_u64("memory.max_usage_in_bytes")?,
+                memory_failcnt: memory.read_u64("memory.failcnt")?,
+                oom_kills: memory.read_stat("memory.oom_control", "oom_kill")?
+                    .unwrap_or(0),
+                pids: pids_dir.read_u64("pids.current")?,
+            }),
+            Backend::V2(ref dir) => {
+                let stat = |name, key| {
+                    dir.read_stat(name, key).map(|value| value.unwrap_or(0))
+                };
+                let cpu_stat =
+                    |key| stat("cpu.stat", key).map(micros_to_duration);
+                Ok(Usage {
+                    cpu_time: cpu_stat("usage_usec")?,
+                    user_time: cpu_stat("user_usec")?,
+                    system_time: cpu_stat("system_usec")?,
+                    // memory.peak only exists since Linux 5.19.
+                    memory_peak: match dir.read_u64("memory.peak") {
+                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+                        result => result?,
+                    },
+                    memory_failcnt: stat("memory.events", "max")?,
+                    oom_kills: stat("memory.events", "oom_kill")?,
+                    pids: dir.read_u64("pids.current")?,
+                })
+            },
+        }
+    }
+
     pub fn procs(&self) -> io::Result<Box<[Pid]>> {
+        let dirs = match self.0 {
+            Backend::V1 {
+                ref cpuacct_dir, ref memory_dir, ref pids_dir, ref freezer_dir
+            } => vec![cpuacct_dir, memory_dir, pids_dir, freezer_dir],
+            Backend::V2(ref dir) => vec![dir],
+        };
         let mut pids = Vec::new();
-        for &dir in &[&self.cpuacct_dir, &self.memory_dir, &self.pids_dir] {
+        for dir in dirs {
             for line in dir.read("cgroup.procs")?.lines() {
                 let pid = line.parse()
                     .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
                 pids.push(Pid::from_raw(pid));
             }
         }
+        pids.sort_by_key(|&pid| i32::from(pid));
         pids.dedup();
         Ok(pids.into_boxed_slice())
     }
+
+    // Kills every process in the group and waits for them to leave it. The
+    // group is frozen while killing, so that nothing forks in between, and
+    // thawed again whatever happens.
+    pub fn kill_all(&mut self) -> io::Result<()> {
+        if let Backend::V2(ref mut dir) = self.0 {
+            match dir.write("cgroup.kill", "1") {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                result => return result.and_then(|()| self.wait_empty()),
+            }
+        }
+        let killed = self.freeze().and_then(|()| self.kill_procs());
+        let thawed = self.thaw();
+        killed.and(thawed)?;
+        self.wait_empty()
+    }
+
+    fn freeze(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + KILL_TIMEOUT;
+        match self.0 {
+            Backend::V1 { ref mut freezer_dir, .. } => {
+                freezer_dir.write("freezer.state", "FROZEN")?;
+                while freezer_dir.read("freezer.state")?.trim() != "FROZEN" {
+                    poll_until(deadline)?;
+                }
+            },
+            Backend::V2(ref mut dir) => {
+                dir.write("cgroup.freeze", "1")?;
+                while dir.read_stat("cgroup.events", "frozen")? != Some(1) {
+                    poll_until(deadline)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn thaw(&mut self) -> io::Result<()> {
+        match self.0 {
+            Backend::V1 { ref mut freezer_dir, .. } =>
+                freezer_dir.write("freezer.state", "THAWED"),
+            Backend::V2(ref mut dir) => dir.write("cgroup.freeze", "0"),
+        }
+    }
+
+    fn kill_procs(&self) -> io::Result<()> {
+        for &pid in self.procs()?.iter() {
+            match signal::kill(pid, Signal::SIGKILL) {
+                Ok(()) | Err(::nix::Error::Sys(Errno::ESRCH)) => (),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_empty(&self) -> io::Result<()> {
+        let deadline = Instant::now() + KILL_TIMEOUT;
+        while !self.procs()?.is_empty() {
+            poll_until(deadline)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CGroup {
+    fn drop(&mut self) {
+        // Best effort, directories with processes left will fail to remove.
+        let _ = self.kill_all();
+    }
+}
+
+impl Root {
+    // Picks v2 if the unified hierarchy is mounted on its own, otherwise v1.
+    // The path is relative to the hierarchy, or to each of them on v1.
+    pub fn detect(path: &Path) -> Root {
+        let mount = Path::new(CGROUP_MOUNT);
+        if mount.join("cgroup.controllers").is_file() {
+            Root::V2(mount.join(path))
+        } else {
+            Root::V1 {
+                cpuacct: mount.join("cpuacct").join(path),
+                memory: mount.join("memory").join(path),
+                pids: mount.join("pids").join(path),
+                freezer: mount.join("freezer").join(path),
+            }
+        }
+    }
+
+    // On v2, the controllers are enabled in the parent of the root and in the
+    // root, which must not contain any process.
+    fn prepare(&self) -> io::Result<()> {
+        match *self {
+            Root::V1 { ref cpuacct, ref memory, ref pids, ref freezer } => {
+                for path in &[cpuacct, memory, pids, freezer] {
+                    fs::create_dir_all(path)?;
+                }
+            },
+            Root::V2(ref path) => {
+                if let Some(parent) = path.parent() {
+                    enable_controllers(parent)?;
+                }
+                fs::create_dir_all(path)?;
+                enable_controllers(path)?;
+            },
+        }
+        Ok(())
+    }
 }
 
 impl CGroupDir {
@@ -79,15 +328,187 @@ impl CGroupDir {
         Ok(result)
     }
 
+    fn read_u64(&self, name: &str) -> io::Result<u64> {
+        self.read(name)?.trim().parse()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+    }
+
+    // Reads a value from a file of "key value" lines. Returns None if the key
+    // is absent, as files gain keys across kernel versions.
+    fn read_stat(&self, name: &str, key: &str) -> io::Result<Option<u64>> {
+        for line in self.read(name)?.lines() {
+            let mut parts = line.split_whitespace();
+            if parts.next() == Some(key) {
+                return parts.next().and_then(|value| value.parse().ok())
+                    .map(Some)
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData));
+            }
+        }
+        Ok(None)
+    }
+
     fn write(&mut self, name: &str, data: &str) -> io::Result<()> {
-        let mut file = File::create(self.0.join(name))?;
-        file.write_all(data.as_bytes())?;
-        Ok(())
+        write_file(&self.0.join(name), data)
+    }
+
+    // Like write, but ignores controller files missing from this kernel,
+    // e.g. swap files when swap accounting is disabled.
+    fn write_optional(&mut self, name: &str, data: &str) -> io::Result<()> {
+        match self.write(name, data) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn write_file(path: &Path, data: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(data.as_bytes())?;
+    Ok(())
+}
+
+// Enabling controllers which are already enabled is a no-op, even in a group
+// with processes.
+fn enable_controllers(path: &Path) -> io::Result<()> {
+    write_file(&path.join("cgroup.subtree_control"), V2_CONTROLLERS)
+        .map_err(|e| io::Error::new(e.kind(), format!(
+            "enable {} in {}: {}", V2_CONTROLLERS, path.display(), e)))
+}
+
+// The hierarchy is missing, mounted read-only or not ours to write to.
+fn is_undelegated(error: &io::Error) -> bool {
+    matches!(error.kind(),
+             io::ErrorKind::NotFound |
+             io::ErrorKind::ReadOnlyFilesystem |
+             io::ErrorKind::PermissionDenied)
+}
+
+fn poll_until(deadline: Instant) -> io::Result<()> {
+    if Instant::now() > deadline {
+        return Err(io::Error::from(io::ErrorKind::TimedOut));
     }
+    thread::sleep(KILL_POLL_INTERVAL);
+    Ok(())
+}
+
+fn max_or(value: Option<u64>) -> String {
+    value.map_or_else(|| String::from("max"), |value| value.to_string())
+}
+
+fn nanos_to_duration(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+fn micros_to_duration(micros: u64) -> Duration {
+    Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1000)
 }
 
 impl Drop for CGroupDir {
     fn drop(&mut self) {
-        fs::remove_dir(&self.0).unwrap();
+        // Nothing sensible to do on failure, and panicking here would take
+        // down the daemon.
+        let _ = fs::remove_dir(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn v2_prepare() {
+        let temp_dir = TempDir::new("jd-cgroup").unwrap();
+        let path = temp_dir.path().join("sandbox");
+        let root = Root::V2(path.clone());
+        let error = root.prepare().unwrap_err();
+        assert!(error.to_string().starts_with("enable +cpu +memory +pids"));
+        fs::create_dir(&path).unwrap();
+        for dir in &[temp_dir.path(), &path] {
+            fs::write(dir.join("cgroup.subtree_control"), "").unwrap();
+        }
+        root.prepare().unwrap();
+        for dir in &[temp_dir.path(), &path] {
+            assert_eq!(fs::read_to_string(dir.join("cgroup.subtree_control"))
+                           .unwrap(),
+                       V2_CONTROLLERS);
+        }
+    }
+
+    #[test]
+    fn with_limits_undelegated() {
+        let temp_dir = TempDir::new("jd-cgroup").unwrap();
+        // No cgroup.subtree_control to enable the controllers in.
+        let root = Root::V2(temp_dir.path().join("sandbox"));
+        assert!(CGroup::with_limits(&root, &Limits::default())
+                    .unwrap().is_none());
+        // A root which is taken by a file is an error of its own.
+        let file = temp_dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let root = Root::V1 {
+            cpuacct: file,
+            memory: temp_dir.path().join("memory"),
+            pids: temp_dir.path().join("pids"),
+            freezer: temp_dir.path().join("freezer"),
+        };
+        assert!(CGroup::with_limits(&root, &Limits::default()).is_err());
+    }
+
+    #[test]
+    fn detect_path() {
+        let path = Path::new("judge/sandbox");
+        match Root::detect(path) {
+            Root::V1 { cpuacct, memory, pids, freezer } => {
+                for dir in &[cpuacct, memory, pids, freezer] {
+                    assert!(dir.starts_with(CGROUP_MOUNT));
+                    assert!(dir.ends_with(path));
+                }
+            },
+            Root::V2(dir) =>
+                assert_eq!(dir, Path::new(CGROUP_MOUNT).join(path)),
+        }
+    }
+
+    #[test]
+    fn v2_thaw_on_error() {
+        let temp_dir = TempDir::new("jd-cgroup").unwrap();
+        let path = temp_dir.path().join("test");
+        fs::create_dir(&path).unwrap();
+        // Without cgroup.events, the group never reports being frozen.
+        fs::write(path.join("cgroup.freeze"), "").unwrap();
+        let mut cgroup = CGroup(Backend::V2(CGroupDir(path.clone())));
+        assert!(cgroup.kill_all().is_err());
+        let freeze = fs::read_to_string(path.join("cgroup.freeze")).unwrap();
+        assert_eq!(freeze, "0");
+        fs::remove_file(path.join("cgroup.freeze")).unwrap();
+    }
+
+    #[test]
+    fn v2_usage() {
+        let temp_dir = TempDir::new("jd-cgroup").unwrap();
+        let path = temp_dir.path().join("test");
+        fs::create_dir(&path).unwrap();
+        let files = [
+            ("cpu.stat", "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n"),
+            ("memory.events", "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n"),
+            ("memory.peak", "1048576\n"),
+            ("pids.current", "2\n"),
+        ];
+        for &(name, data) in &files {
+            fs::write(path.join(name), data).unwrap();
+        }
+        let cgroup = CGroup(Backend::V2(CGroupDir(path.clone())));
+        assert_eq!(cgroup.usage().unwrap(), Usage {
+            cpu_time: Duration::from_micros(1500),
+            user_time: Duration::from_micros(1000),
+            system_time: Duration::from_micros(500),
+            memory_peak: 1048576,
+            memory_failcnt: 3,
+            oom_kills: 1,
+            pids: 2,
+        });
+        for &(name, _) in &files {
+            fs::remove_file(path.join(name)).unwrap();
+        }
     }
 }
@@ -0,0 +1,324 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::str;
+
+const DEFAULT_EPSILON: f64 = 1e-6;
+// Tokens are read whole to be compared. A longer token is a mismatch, so that
+// a submission cannot make the checker buffer its whole output.
+const MAX_TOKEN_LEN: usize = 1048576;
+
+// Compares the output of a submission with the expected one. Both are read as
+// streams, so neither needs to fit in memory.
+pub trait Checker: Sync {
+    fn check(&self, actual: &mut dyn Read, expected: &mut dyn Read)
+        -> io::Result<bool>;
+}
+
+// How the output of a problem is checked, as given by its config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckerConfig {
+    Exact,
+    Tokens,
+    CaseInsensitive,
+    Float {
+        #[serde(default = "default_epsilon")]
+        absolute: f64,
+        #[serde(default = "default_epsilon")]
+        relative: f64,
+    },
+    #[default]
+    Lines,
+}
+
+// Byte for byte.
+pub struct ExactChecker;
+
+// Whitespace separated tokens.
+pub struct TokenChecker;
+
+// Whitespace separated tokens, ignoring ASCII case.
+pub struct CaseInsensitiveChecker;
+
+// Whitespace separated tokens, where numbers match if they are within either
+// epsilon of the expected one.
+pub struct FloatChecker {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+// Line by line, ignoring trailing whitespace on each line and trailing blank
+// lines.
+pub struct LineChecker;
+
+impl CheckerConfig {
+    pub fn build(self) -> Box<dyn Checker> {
+        match self {
+            CheckerConfig::Exact => Box::new(ExactChecker),
+            CheckerConfig::Tokens => Box::new(TokenChecker),
+            CheckerConfig::CaseInsensitive => Box::new(CaseInsensitiveChecker),
+            CheckerConfig::Float { absolute, relative } =>
+                Box::new(FloatChecker { absolute, relative }),
+            CheckerConfig::Lines => Box::new(LineChecker),
+        }
+    }
+}
+
+impl Checker for ExactChecker {
+    fn check(&self, actual: &mut dyn Read, expected: &mut dyn Read)
+        -> io::Result<bool> {
+        let mut actual = BufReader::new(actual);
+        let mut expected = BufReader::new(expected);
+        loop {
+            let len = {
+                let actual_buf = actual.fill_buf()?;
+                let expected_buf = expected.fill_buf()?;
+                let len = actual_buf.len().min(expected_buf.len());
+                if actual_buf[..len] != expected_buf[..len] {
+                    return Ok(false);
+                }
+                if len == 0 {
+                    return Ok(actual_buf.is_empty() && expected_buf.is_empty());
+                }
+                len
+            };
+            actual.consume(len);
+            expected.consume(len);
+        }
+    }
+}
+
+impl Checker for TokenChecker {
+    fn check(&self, actual: &mut dyn Read, expected: &mut dyn Read)
+        -> io::Result<bool> {
+        compare_tokens(actual, expected, |a, b| a == b)
+    }
+}
+
+impl Checker for CaseInsensitiveChecker {
+    fn check(&self, actual: &mut dyn Read, expected: &mut dyn Read)
+        -> io::Result<bool> {
+        compare_tokens(actual, expected, |a, b| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Checker for FloatChecker {
+    fn check(&self, actual: &mut dyn Read, expected: &mut dyn Read)
+        -> io::Result<bool> {
+        compare_tokens(actual, expected, |a, b| {
+            match (parse_float(a), parse_float(b)) {
+                (Some(a), Some(b)) => {
+                    let error = (a - b).abs();
+                    a == b || error <= self.absolute ||
+                        error <= self.relative * b.abs()
+                },
+                _ => a == b,
+            }
+        })
+    }
+}
+
+// Lines match if they are equal up to where they differ, and the rest of both
+// is whitespace. Reading stops there, so long lines are never buffered.
+impl Checker for LineChecker {
+    fn check(&self, actual: &mut dyn Read, expected: &mut dyn Read)
+        -> io::Result<bool> {
+        let mut actual = BufReader::new(actual);
+        let mut expected = BufReader::new(expected);
+        loop {
+            skip_common(&mut actual, &mut expected)?;
+            if !skip_blank_line(&mut actual)? ||
+                !skip_blank_line(&mut expected)? {
+                return Ok(false);
+            }
+            if actual.fill_buf()?.is_empty() &&
+                expected.fill_buf()?.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn default_epsilon() -> f64 {
+    DEFAULT_EPSILON
+}
+
+fn compare_tokens<F: Fn(&[u8], &[u8]) -> bool>(
+    actual: &mut dyn Read,
+    expected: &mut dyn Read,
+    equal: F,
+) -> io::Result<bool> {
+    let mut actual = BufReader::new(actual);
+    let mut expected = BufReader::new(expected);
+    let mut actual_token = Vec::new();
+    let mut expected_token = Vec::new();
+    loop {
+        let has_actual = next_token(&mut actual, &mut actual_token)?;
+        let has_expected = next_token(&mut expected, &mut expected_token)?;
+        if has_actual != has_expected {
+            return Ok(false);
+        }
+        if !has_actual {
+            return Ok(true);
+        }
+        if actual_token.len() > MAX_TOKEN_LEN ||
+            expected_token.len() > MAX_TOKEN_LEN {
+            return Ok(false);
+        }
+        if !equal(&actual_token, &expected_token) {
+            return Ok(false);
+        }
+    }
+}
+
+// Reads the next whitespace separated token into token, up to one byte over
+// the limit. Returns false at the end of input.
+fn next_token<R: BufRead>(reader: &mut R, token: &mut Vec<u8>)
+    -> io::Result<bool> {
+    token.clear();
+    loop {
+        let (used, done) = {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(!token.is_empty());
+            }
+            let mut used = 0;
+            let mut done = false;
+            for &c in buf {
+                used += 1;
+                if !c.is_ascii_whitespace() {
+                    token.push(c);
+                    if token.len() > MAX_TOKEN_LEN {
+                        done = true;
+                        break;
+                    }
+                } else if !token.is_empty() {
+                    done = true;
+                    break;
+                }
+            }
+            (used, done)
+        };
+        reader.consume(used);
+        if done {
+            return Ok(true);
+        }
+    }
+}
+
+fn parse_float(token: &[u8]) -> Option<f64> {
+    str::from_utf8(token).ok()?.parse().ok().filter(|f: &f64| !f.is_nan())
+}
+
+// Consumes what both readers have in common.
+fn skip_common<A: BufRead, B: BufRead>(a: &mut A, b: &mut B)
+    -> io::Result<()> {
+    loop {
+        let len = {
+            let a_buf = a.fill_buf()?;
+            let b_buf = b.fill_buf()?;
+            a_buf.iter().zip(b_buf).take_while(|&(a, b)| a == b).count()
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+// Consumes the rest of the line. Returns false if it is not blank.
+fn skip_blank_line<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    loop {
+        let (used, done) = {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(true);
+            }
+            match buf.iter()
+                .position(|&c| c == b'\n' || !c.is_ascii_whitespace()) {
+                Some(i) if buf[i] != b'\n' => return Ok(false),
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            }
+        };
+        reader.consume(used);
+        if done {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml;
+
+    fn check(checker: &dyn Checker, actual: &[u8], expected: &[u8]) -> bool {
+        checker.check(&mut &actual[..], &mut &expected[..]).unwrap()
+    }
+
+    #[test]
+    fn exact() {
+        assert!(check(&ExactChecker, b"1 2\n", b"1 2\n"));
+        assert!(!check(&ExactChecker, b"1 2\n", b"1 2"));
+        assert!(!check(&ExactChecker, b"1 2\r\n", b"1 2\n"));
+        let long = vec![b'x'; 100000];
+        assert!(check(&ExactChecker, &long, &long));
+        assert!(!check(&ExactChecker, &long, &long[1..]));
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(check(&TokenChecker, b"1  2\n\n3", b"1 2\r\n3\r\n"));
+        assert!(!check(&TokenChecker, b"1 2 3", b"1 2"));
+        assert!(!check(&TokenChecker, b"12", b"1 2"));
+        assert!(check(&CaseInsensitiveChecker, b"Yes\nNO", b"YES no"));
+        assert!(!check(&CaseInsensitiveChecker, b"yes", b"yes no"));
+    }
+
+    #[test]
+    fn floats() {
+        let checker = FloatChecker { absolute: 1e-6, relative: 1e-6 };
+        assert!(check(&checker, b"0.1000001 abc", b"0.1 abc"));
+        assert!(check(&checker, b"1000000.5", b"1000000"));
+        assert!(!check(&checker, b"0.100002", b"0.1"));
+        assert!(!check(&checker, b"abc", b"ABC"));
+        assert!(!check(&checker, b"nan", b"0"));
+        assert!(check(&checker, b"inf", b"inf"));
+    }
+
+    #[test]
+    fn lines() {
+        assert!(check(&LineChecker, b"1 2\n3\n", b"1 2\r\n3\r\n"));
+        assert!(check(&LineChecker, b"1 2  \n3", b"1 2\n3\n\n\n"));
+        assert!(!check(&LineChecker, b"1 2\n3\n", b"1  2\n3\n"));
+        assert!(!check(&LineChecker, b"1 2\n", b"1 2\n3\n"));
+        assert!(!check(&LineChecker, b"1 2\n\n3\n", b"1 2\n3\n"));
+        assert!(check(&LineChecker, b"1 2 \n3 \t\n \n", b"1 2\t\n3\n"));
+        assert!(!check(&LineChecker, b"1 2 \n", b"1 2 3\n"));
+        let mut long = vec![b'x'; 10000000];
+        assert!(check(&LineChecker, &long, &long));
+        assert!(!check(&LineChecker, &long, &long[1..]));
+        long.extend_from_slice(b"  \n\n");
+        assert!(check(&LineChecker, &long, &long[..long.len() - 4]));
+    }
+
+    #[test]
+    fn long_tokens() {
+        let long = vec![b'1'; MAX_TOKEN_LEN];
+        assert!(check(&TokenChecker, &long, &long));
+        let longer = vec![b'1'; MAX_TOKEN_LEN + 1];
+        assert!(!check(&TokenChecker, &longer, &longer));
+        assert!(!check(&TokenChecker, &vec![b'1'; 10000000], b"1"));
+    }
+
+    #[test]
+    fn parse_config() {
+        let parse = |s| serde_yaml::from_str::<CheckerConfig>(s).unwrap();
+        assert_eq!(parse("tokens"), CheckerConfig::Tokens);
+        assert_eq!(parse("case-insensitive"), CheckerConfig::CaseInsensitive);
+        assert_eq!(parse("{float: {absolute: 0.001}}"),
+                   CheckerConfig::Float { absolute: 0.001, relative: 1e-6 });
+        assert!(serde_yaml::from_str::<CheckerConfig>("fuzzy").is_err());
+    }
+}
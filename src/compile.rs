@@ -1,33 +1,107 @@
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
-use std::thread;
-use sandbox::{self, ExecuteResult, Pipe, Port, Sandbox};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use bincode;
+use libc;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd;
+use cgroup::{self, CGroup};
+use sandbox::{self, ExecuteOptions, ExecuteResult, Limit, Limits, Pipe, Port,
+              Sandbox, SandboxError, SandboxResult};
+use seccomp::Profile;
 use tempdir::TempDir;
 use util::{self, Pool};
+use verdict::{ExitStatus, Status, Verdict};
+use zip::ZipArchive;
+
+// Expands to the source files in the compiler args.
+const SOURCES_ARG: &str = "{sources}";
+const MAX_SOURCE_LEN: u64 = 16777216;
+// Captures the compiler output in /in.
+const OUTPUT_NAME: &str = ".output";
+// Names in /in which are used by the sandbox and the judge, and cannot be
+// taken by files. So are the names of the ports.
+const RESERVED_NAMES: &[&str] = &[OUTPUT_NAME, sandbox::CGROUP_SOCKET_NAME];
 
 pub trait Compiler : Sync {
-    fn compile(&self, source: &[u8], pool: &Pool<Sandbox>) -> Target;
+    // The extras are provided by the problem, and take precedence over
+    // submitted files of the same path.
+    fn compile(&self, source: &Source, extras: &Files, pool: &Pool<Sandbox>)
+        -> Result<Target, CompileError>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Source {
+    // Written to the code file of the language.
+    Single(Vec<u8>),
+    Files(Files),
 }
 
+// Files by path relative to /in.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Files(BTreeMap<PathBuf, Vec<u8>>);
+
 pub struct BinaryCompiler {
     compiler_file: PathBuf,
     compiler_args: Box<[String]>,
     code_file: PathBuf,
+    // Extensions of other files which are passed as sources.
+    source_exts: Box<[String]>,
     execute_file: PathBuf,
     execute_args: Box<[String]>,
+    limits: CompileLimits,
+    seccomp: Profile,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompileLimits {
+    pub cpu_time: Duration,
+    pub wall_time: Duration,
+    pub memory: u64,
+    pub pids: u64,
+    // Bytes of compiler diagnostics.
+    pub output: u64,
 }
 
 pub struct Interpreter {
     code_file: PathBuf,
     execute_file: PathBuf,
     execute_args: Box<[String]>,
+    seccomp: Profile,
 }
 
 pub struct Target {
     package_dir: TempDir,
     execute_file: PathBuf,
     execute_args: Box<[String]>,
+    // Applies to the target, not to the compiler.
+    seccomp: Profile,
+}
+
+// Output of a sandboxed process collected through a fifo.
+struct OutputCapture {
+    writer: File,
+    thread: JoinHandle<io::Result<(Vec<u8>, bool)>>,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Failed {
+        exit_status: ExitStatus,
+        limit_exceeded: Option<Limit>,
+        output: String,
+    },
+    InvalidSource(String),
+    Sandbox(SandboxError),
+    Io(io::Error),
 }
 
 impl BinaryCompiler {
@@ -35,45 +109,226 @@ impl BinaryCompiler {
         compiler_file: PathBuf,
         compiler_args: Box<[String]>,
         code_file: PathBuf,
+        source_exts: Box<[String]>,
         execute_file: PathBuf,
         execute_args: Box<[String]>,
+        limits: CompileLimits,
     ) -> BinaryCompiler {
         BinaryCompiler {
             compiler_file,
             compiler_args,
             code_file,
+            source_exts,
             execute_file,
             execute_args,
+            limits,
+            seccomp: Profile::default(),
         }
     }
+
+    pub fn with_seccomp(mut self, seccomp: Profile) -> BinaryCompiler {
+        self.seccomp = seccomp;
+        self
+    }
+}
+
+impl Default for CompileLimits {
+    fn default() -> CompileLimits {
+        CompileLimits {
+            cpu_time: Duration::from_secs(10),
+            wall_time: Duration::from_secs(20),
+            memory: 536870912,
+            pids: 64,
+            output: 65536,
+        }
+    }
+}
+
+impl Source {
+    fn write_to(&self, dir: &Path, code_file: &Path) -> io::Result<()> {
+        match *self {
+            Source::Single(ref code) =>
+                File::create(dir.join(code_file))?.write_all(code),
+            Source::Files(ref files) => files.write_to(dir),
+        }
+    }
+}
+
+impl Files {
+    pub fn new() -> Files {
+        Files(BTreeMap::new())
+    }
+
+    // Paths must be relative and must not leave the directory. A file cannot
+    // be the directory of another.
+    pub fn insert(&mut self, path: PathBuf, data: Vec<u8>)
+        -> Result<(), CompileError> {
+        let error = |message: &str| Err(CompileError::InvalidSource(
+            format!("{}: {}", message, path.display())));
+        if path.as_os_str().is_empty() ||
+            !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return error("invalid path");
+        }
+        if path.components().next().is_some_and(|c| {
+            RESERVED_NAMES.iter().any(|name| c.as_os_str() == *name) ||
+                Port::all().iter().any(|port| c.as_os_str() == port.name())
+        }) {
+            return error("reserved path");
+        }
+        let is_dir = self.0.range::<Path, _>(
+            (Bound::Excluded(path.as_path()), Bound::Unbounded))
+            .next().is_some_and(|(other, _)| other.starts_with(&path));
+        if is_dir || path.ancestors().skip(1)
+            .any(|ancestor| self.0.contains_key(ancestor)) {
+            return error("conflicting path");
+        }
+        self.0.insert(path, data);
+        Ok(())
+    }
+
+    pub fn from_zip<R: Read + Seek>(reader: R) -> Result<Files, CompileError> {
+        let invalid = |e: &dyn fmt::Display| {
+            CompileError::InvalidSource(format!("zip: {}", e))
+        };
+        let mut archive = ZipArchive::new(reader).map_err(|e| invalid(&e))?;
+        let mut files = Files::new();
+        let mut total_len = 0;
+        for index in 0..archive.len() {
+            let file = archive.by_index(index).map_err(|e| invalid(&e))?;
+            if file.name().ends_with('/') {
+                continue;
+            }
+            let path = PathBuf::from(file.name());
+            let mut data = Vec::new();
+            file.take(MAX_SOURCE_LEN - total_len + 1).read_to_end(&mut data)?;
+            total_len += data.len() as u64;
+            if total_len > MAX_SOURCE_LEN {
+                return Err(invalid(&"too large"));
+            }
+            files.insert(path, data)?;
+        }
+        Ok(files)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.0.keys().map(PathBuf::as_path)
+    }
+
+    fn write_to(&self, dir: &Path) -> io::Result<()> {
+        for (path, data) in &self.0 {
+            let path = dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::create(path)?.write_all(data)?;
+        }
+        Ok(())
+    }
 }
 
 impl Compiler for BinaryCompiler {
-    fn compile(&self, source: &[u8], pool: &Pool<Sandbox>) -> Target {
-        let compiler_file = self.compiler_file.clone();
-        let compiler_args = self.compiler_args.clone();
-        let mut sandbox = pool.get_one();
-        let mut code_file =
-            File::create(sandbox.in_dir().join(&self.code_file)).unwrap();
-        code_file.write_all(&source).unwrap();
-        // TODO(iceboy): stdin, stdout, stderr, cgroup
-        let status = sandbox.execute(
-            compiler_file,
-            compiler_args,
-            sandbox::default_envs(),
+    fn compile(&self, source: &Source, extras: &Files, pool: &Pool<Sandbox>)
+        -> Result<Target, CompileError> {
+        // Without the sources in the args, only the code file is compiled.
+        let multiple = match *source {
+            Source::Single(_) => false,
+            Source::Files(ref files) => files.paths().nth(1).is_some(),
+        } || extras.paths().next().is_some();
+        if multiple &&
+            !self.compiler_args.iter().any(|arg| arg == SOURCES_ARG) {
+            return Err(CompileError::InvalidSource(
+                String::from("multiple source files are not supported")));
+        }
+        let mut sandbox = Sandbox::get_one(pool)?;
+        let target = self.compile_in(source, extras, &mut sandbox);
+        sandbox.recycle(pool)?;
+        target
+    }
+}
+
+impl BinaryCompiler {
+    fn compile_in(&self, source: &Source, extras: &Files, sandbox: &mut Sandbox)
+        -> Result<Target, CompileError> {
+        source.write_to(&sandbox.in_dir(), &self.code_file)?;
+        extras.write_to(&sandbox.in_dir())?;
+        let mut args = Vec::with_capacity(self.compiler_args.len());
+        for arg in self.compiler_args.iter() {
+            if arg == SOURCES_ARG {
+                args.extend(self.source_paths(source, extras).map(
+                    |path| Path::new("/in").join(path).display().to_string()));
+            } else {
+                args.push(arg.clone());
+            }
+        }
+        let output_path = sandbox.in_dir().join(OUTPUT_NAME);
+        let capture = OutputCapture::new(&output_path, self.limits.output)?;
+        let cgroup_limits = cgroup::Limits {
+            memory: Some(self.limits.memory),
+            swap: None,
+            pids: Some(self.limits.pids),
+        };
+        let mut cgroup =
+            CGroup::with_limits(sandbox.cgroup_root(), &cgroup_limits)?;
+        let limits = Limits {
+            cpu_time: Some(self.limits.cpu_time),
+            wall_time: Some(self.limits.wall_time),
+            processes: if cgroup.is_some() {
+                None
+            } else {
+                Some(self.limits.pids)
+            },
+            ..Default::default()
+        };
+        // TODO(iceboy): stdin
+        let result = sandbox.execute(
+            self.compiler_file.clone(),
+            args.into_boxed_slice(),
             PathBuf::from("/out"),
             Box::new([]),
-            None).unwrap();
-        assert_eq!(status, 0);
-        let package_dir = TempDir::new("jd-package").unwrap();
-        util::copy_dir(&sandbox.out_dir(), package_dir.path());
-        sandbox.cleanup();
-        pool.put(sandbox);
-        Target {
+            Box::new([(Path::new("/in").join(OUTPUT_NAME), Port::stdout()),
+                      (Path::new("/in").join(OUTPUT_NAME), Port::stderr())]),
+            ExecuteOptions {
+                limits,
+                cgroup: cgroup.as_mut(),
+                ..Default::default()
+            });
+        let (output, output_exceeded) = capture.finish()?;
+        let result = result?;
+        let limit_exceeded = result.limit_exceeded
+            .or(if output_exceeded { Some(Limit::Output) } else { None });
+        if limit_exceeded.is_some() || !result.exit_status.success() {
+            return Err(CompileError::Failed {
+                exit_status: result.exit_status,
+                limit_exceeded,
+                output: String::from_utf8_lossy(&output).into_owned(),
+            });
+        }
+        let package_dir = TempDir::new("jd-package")?;
+        util::copy_dir(&sandbox.out_dir(), package_dir.path())?;
+        Ok(Target {
             package_dir,
             execute_file: self.execute_file.clone(),
             execute_args: self.execute_args.clone(),
-        }
+            seccomp: self.seccomp.clone(),
+        })
+    }
+
+    // The code file comes first for single sources, followed by other files
+    // with a source extension, in path order.
+    fn source_paths<'a>(&'a self, source: &'a Source, extras: &'a Files)
+        -> impl Iterator<Item = &'a Path> {
+        let (code_file, files) = match *source {
+            Source::Single(_) => (Some(self.code_file.as_path()), None),
+            Source::Files(ref files) => (None, Some(files)),
+        };
+        let mut paths = files.into_iter().flat_map(Files::paths)
+            .chain(extras.paths())
+            .filter(|path| Some(*path) != code_file && path.extension()
+                .is_some_and(|ext| self.source_exts.iter().any(|e| ext == e.as_str())))
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        code_file.into_iter().chain(paths)
     }
 }
 
@@ -83,68 +338,281 @@ impl Interpreter {
         execute_file: PathBuf,
         execute_args: Box<[String]>,
     ) -> Interpreter {
-        Interpreter { code_file, execute_file, execute_args }
+        Interpreter {
+            code_file,
+            execute_file,
+            execute_args,
+            seccomp: Profile::default(),
+        }
+    }
+
+    pub fn with_seccomp(mut self, seccomp: Profile) -> Interpreter {
+        self.seccomp = seccomp;
+        self
     }
 }
 
 impl Compiler for Interpreter {
-    fn compile(&self, source: &[u8], _: &Pool<Sandbox>) -> Target {
-        let package_dir = TempDir::new("jd-package").unwrap();
-        let mut file =
-            File::create(package_dir.path().join(&self.code_file)).unwrap();
-        file.write_all(source).unwrap();
-        drop(file);
-        Target {
+    fn compile(&self, source: &Source, extras: &Files, _: &Pool<Sandbox>)
+        -> Result<Target, CompileError> {
+        let package_dir = TempDir::new("jd-package")?;
+        source.write_to(package_dir.path(), &self.code_file)?;
+        extras.write_to(package_dir.path())?;
+        Ok(Target {
             package_dir,
             execute_file: self.execute_file.clone(),
             execute_args: self.execute_args.clone(),
-        }
+            seccomp: self.seccomp.clone(),
+        })
     }
 }
 
 impl Target {
+    // The args are appended to the ones given by the compiler, and the
+    // seccomp profile of the compiler replaces the one in the options.
     pub fn execute(
         &self,
         sandbox: &mut Sandbox,
-        envs: Box<[String]>,
+        args: &[String],
         pipes: Box<[(Pipe, Port)]>,
-        cgroup_file: Option<PathBuf>
-    ) -> ExecuteResult {
+        files: Box<[(PathBuf, Port)]>,
+        options: ExecuteOptions,
+    ) -> SandboxResult<ExecuteResult> {
         let install_dir = sandbox.in_dir().join("package");
-        fs::create_dir(&install_dir).unwrap();
-        util::copy_dir(self.package_dir.path(), &install_dir);
+        fs::create_dir(&install_dir)
+            .and_then(|()| util::copy_dir(self.package_dir.path(), &install_dir))
+            .map_err(|e| {
+                SandboxError::Runtime(format!("install package: {}", e))
+            })?;
         sandbox.execute(
             PathBuf::from("/in/package").join(&self.execute_file),
-            self.execute_args.clone(),
-            envs,
+            self.execute_args.iter().chain(args).cloned().collect(),
             PathBuf::from("/in/package"),
             pipes,
-            cgroup_file)
-    }
-}
-
-pub fn run(user_target: Target, judge_target: Target, pool: &Pool<Sandbox>) {
-    let (mut user_sandbox, mut judge_sandbox) = pool.get_two();
-    let (pin, pout) = Pipe::new();
-    let user_thread = thread::spawn(move || {
-        let user_result = user_target.execute(
-            &mut user_sandbox,
-            sandbox::default_envs(),
-            Box::new([(pout, Port::stdout())]),
-            None);
-        (user_result, user_sandbox)
-    });
-    let judge_result = judge_target.execute(
-        &mut judge_sandbox,
-        sandbox::default_envs(),
-        Box::new([(pin, Port::extra())]),
-        None);
-    judge_sandbox.cleanup();
-    pool.put(judge_sandbox);
-    let (user_result, mut user_sandbox) = user_thread.join().unwrap();
-    user_sandbox.cleanup();
-    pool.put(user_sandbox);
-    // TODO(iceboy)
-    println!("User return code: {}", user_result.unwrap());
-    println!("Judge return code: {}", judge_result.unwrap());
+            files,
+            ExecuteOptions {
+                seccomp: Some(self.seccomp.clone()),
+                ..options
+            })
+    }
+
+    // Saves the package and how to execute it into dir, which must not exist.
+    pub(crate) fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir(dir)?;
+        fs::create_dir(dir.join("package"))?;
+        util::copy_dir(self.package_dir.path(), &dir.join("package"))?;
+        let file = File::create(dir.join("target"))?;
+        let target = (&self.execute_file, &self.execute_args, &self.seccomp);
+        bincode::serialize_into(file, &target).map_err(io::Error::other)
+    }
+
+    pub(crate) fn load(dir: &Path) -> io::Result<Target> {
+        let (execute_file, execute_args, seccomp) =
+            bincode::deserialize_from(File::open(dir.join("target"))?)
+                .map_err(io::Error::other)?;
+        let package_dir = TempDir::new("jd-package")?;
+        util::copy_dir(&dir.join("package"), package_dir.path())?;
+        Ok(Target { package_dir, execute_file, execute_args, seccomp })
+    }
+}
+
+impl CompileError {
+    pub fn verdict(&self) -> Verdict {
+        match *self {
+            CompileError::Failed { exit_status, limit_exceeded, ref output } => {
+                let message = match limit_exceeded {
+                    Some(Limit::CpuTime) | Some(Limit::WallTime) =>
+                        format!("compile time limit exceeded\n{}", output),
+                    Some(Limit::Memory) =>
+                        format!("compile memory limit exceeded\n{}", output),
+                    Some(Limit::Output) =>
+                        format!("compile output limit exceeded\n{}", output),
+                    None => output.clone(),
+                };
+                let mut verdict =
+                    Verdict::new(Status::CompileError).with_message(&message);
+                verdict.exit_status = Some(exit_status);
+                verdict
+            },
+            CompileError::InvalidSource(ref message) =>
+                Verdict::new(Status::CompileError).with_message(message),
+            CompileError::Sandbox(ref e) =>
+                Verdict::new(Status::SystemError).with_message(&e.to_string()),
+            CompileError::Io(ref e) =>
+                Verdict::new(Status::SystemError).with_message(&e.to_string()),
+        }
+    }
+}
+
+impl From<SandboxError> for CompileError {
+    fn from(e: SandboxError) -> CompileError {
+        CompileError::Sandbox(e)
+    }
+}
+
+impl From<io::Error> for CompileError {
+    fn from(e: io::Error) -> CompileError {
+        CompileError::Io(e)
+    }
+}
+
+impl OutputCapture {
+    // Creates a fifo at path and reads whatever the sandboxed process writes
+    // to it. We hold a writer so that the reader does not see EOF before the
+    // process opens the fifo. Past the limit the reader is closed, so further
+    // writes fail.
+    fn new(path: &Path, limit: u64) -> io::Result<OutputCapture> {
+        unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+            .map_err(io::Error::other)?;
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        let writer = OpenOptions::new().write(true).open(path)?;
+        fcntl::fcntl(reader.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))
+            .map_err(io::Error::other)?;
+        let thread = thread::spawn(move || {
+            let mut output = Vec::new();
+            reader.take(limit + 1).read_to_end(&mut output)?;
+            let exceeded = output.len() as u64 > limit;
+            output.truncate(limit as usize);
+            Ok((output, exceeded))
+        });
+        Ok(OutputCapture { writer, thread })
+    }
+
+    // Call once the process is done. Returns the output and whether it
+    // exceeded the limit.
+    fn finish(self) -> io::Result<(Vec<u8>, bool)> {
+        drop(self.writer);
+        self.thread.join().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_sh(script: &str, limits: CompileLimits) -> CompileError {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let compiler = BinaryCompiler::new(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from(script)]),
+            PathBuf::from("foo.sh"),
+            Box::new([]),
+            PathBuf::from("foo"),
+            Box::new([String::from("foo")]),
+            limits);
+        match compiler.compile(&Source::Single(Vec::new()), &Files::new(), &pool) {
+            Ok(_) => panic!("compiled"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn files_from_zip() {
+        use std::io::Cursor;
+        use zip::ZipWriter;
+        use zip::write::FileOptions;
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.add_directory("lib/", FileOptions::default()).unwrap();
+        writer.start_file("lib/util.h", FileOptions::default()).unwrap();
+        writer.write_all(b"int util(void);").unwrap();
+        writer.start_file("main.c", FileOptions::default()).unwrap();
+        writer.write_all(b"int main(void) {}").unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        let files = Files::from_zip(Cursor::new(data)).unwrap();
+        assert_eq!(files.paths().collect::<Vec<_>>(),
+                   [Path::new("lib/util.h"), Path::new("main.c")]);
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("../evil.c", FileOptions::default()).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert!(matches!(Files::from_zip(Cursor::new(data)),
+                         Err(CompileError::InvalidSource(_))));
+    }
+
+    #[test]
+    fn files_reserved() {
+        let mut files = Files::new();
+        for path in &[".output", "cgroup", "stdin", "stdout/a.c", "stderr",
+                      "extra"] {
+            assert!(matches!(files.insert(PathBuf::from(path), Vec::new()),
+                             Err(CompileError::InvalidSource(_))));
+        }
+        files.insert(PathBuf::from("lib/stdin"), Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn files_conflict() {
+        let mut files = Files::new();
+        files.insert(PathBuf::from("a"), Vec::new()).unwrap();
+        files.insert(PathBuf::from("a"), b"a".to_vec()).unwrap();
+        assert!(files.insert(PathBuf::from("a/b"), Vec::new()).is_err());
+        files.insert(PathBuf::from("a.c"), Vec::new()).unwrap();
+        files.insert(PathBuf::from("b/c/d"), Vec::new()).unwrap();
+        assert!(files.insert(PathBuf::from("b"), Vec::new()).is_err());
+        assert!(files.insert(PathBuf::from("b/c"), Vec::new()).is_err());
+        files.insert(PathBuf::from("b/e"), Vec::new()).unwrap();
+        assert_eq!(files.paths().count(), 4);
+    }
+
+    #[test]
+    fn multiple_sources_not_supported() {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let compiler = BinaryCompiler::new(
+            PathBuf::from("/bin/cp"),
+            Box::new([String::from("cp"), String::from("/in/foo.sh"),
+                      String::from("/out/foo")]),
+            PathBuf::from("foo.sh"),
+            Box::new([String::from("sh")]),
+            PathBuf::from("foo"),
+            Box::new([String::from("foo")]),
+            CompileLimits::default());
+        let mut extras = Files::new();
+        extras.insert(PathBuf::from("lib.sh"), Vec::new()).unwrap();
+        assert!(matches!(
+            compiler.compile(&Source::Single(Vec::new()), &extras, &pool),
+            Err(CompileError::InvalidSource(_))));
+        let mut files = Files::new();
+        files.insert(PathBuf::from("foo.sh"), Vec::new()).unwrap();
+        files.insert(PathBuf::from("lib.sh"), Vec::new()).unwrap();
+        assert!(matches!(
+            compiler.compile(&Source::Files(files), &Files::new(), &pool),
+            Err(CompileError::InvalidSource(_))));
+        assert!(compiler.compile(
+            &Source::Single(Vec::new()), &Files::new(), &pool).is_ok());
+    }
+
+    #[test]
+    fn compile_time_limit() {
+        let error = compile_sh("while :; do :; done", CompileLimits {
+            cpu_time: Duration::from_millis(200),
+            ..Default::default()
+        });
+        let verdict = error.verdict();
+        assert_eq!(verdict.status, Status::CompileError);
+        assert!(verdict.message.starts_with("compile time limit exceeded"));
+    }
+
+    #[test]
+    fn compile_output_limit() {
+        let error = compile_sh("echo error; yes", CompileLimits {
+            output: 1024,
+            ..Default::default()
+        });
+        match error {
+            CompileError::Failed { limit_exceeded, ref output, .. } => {
+                assert_eq!(limit_exceeded, Some(Limit::Output));
+                assert_eq!(output.len(), 1024);
+                assert!(output.starts_with("error\n"));
+            },
+            e => panic!("{:?}", e),
+        }
+    }
 }
@@ -1,12 +1,24 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use bincode;
 use linear_map::LinearMap;
 use serde_yaml;
 use shlex;
-use compile::{BinaryCompiler, Compiler, Interpreter};
+use cache::{Cache, CachedCompiler};
+use case::{self, CaseError, Quantity};
+use compile::{BinaryCompiler, CompileLimits, Compiler, Interpreter};
+use seccomp::Profile;
 
 pub struct Registry {
-    compilers: HashMap<String, Box<Compiler>>,
+    compilers: HashMap<String, RegistryEntry>,
+    cache: Option<Cache>,
+}
+
+// The fingerprint covers the id and config of the compiler, and keys the
+// compile cache.
+struct RegistryEntry {
+    compiler: Box<dyn Compiler>,
+    fingerprint: Box<[u8]>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -16,8 +28,29 @@ struct CompilerConfig {
     compiler_file: Option<PathBuf>,
     compiler_args: Option<String>,
     code_file: PathBuf,
+    source_exts: Option<Vec<String>>,
     execute_file: PathBuf,
     execute_args: String,
+    limits: Option<LimitsConfig>,
+    seccomp: Option<SeccompConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct LimitsConfig {
+    cpu_time: Option<Quantity>,
+    wall_time: Option<Quantity>,
+    memory: Option<Quantity>,
+    pids: Option<u64>,
+    output: Option<Quantity>,
+}
+
+// Exactly one of allow and deny, each a list of syscall names.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SeccompConfig {
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
 }
 
 impl Registry {
@@ -25,32 +58,62 @@ impl Registry {
         lazy_static! {
             static ref BUILTIN_REGISTRY: Registry = Registry {
                 compilers: parse_compilers_yaml(
-                    include_bytes!("data/compilers.yaml")),
+                    include_bytes!("data/compilers.yaml")).unwrap(),
+                cache: None,
             };
         }
         &BUILTIN_REGISTRY
     }
 
-    pub fn get_compiler(&self, id: &str) -> Option<&Compiler> {
-        self.compilers.get(id).map(Box::as_ref)
+    // The builtin compilers, which compile through the cache.
+    pub fn builtin_with_cache(cache: Cache) -> Registry {
+        Registry {
+            compilers: parse_compilers_yaml(
+                include_bytes!("data/compilers.yaml")).unwrap(),
+            cache: Some(cache),
+        }
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    pub fn get_compiler(&self, id: &str) -> Option<CachedCompiler<'_>> {
+        self.compilers.get(id).map(|entry| {
+            CachedCompiler::new(entry.compiler.as_ref(), &entry.fingerprint,
+                                self.cache.as_ref())
+        })
     }
 }
 
-fn parse_compilers_yaml(v: &[u8]) -> HashMap<String, Box<Compiler>> {
+fn parse_compilers_yaml(v: &[u8])
+    -> Result<HashMap<String, RegistryEntry>, String> {
     let configs: LinearMap<String, CompilerConfig> =
-        serde_yaml::from_slice(v).unwrap();
+        serde_yaml::from_slice(v).map_err(|e| e.to_string())?;
     configs.into_iter().map(|(id, config)| {
-        (id, match config.kind.as_ref() {
+        let fingerprint =
+            bincode::serialize(&(&id, &config)).unwrap().into_boxed_slice();
+        let seccomp = parse_seccomp(config.seccomp.as_ref())
+            .map_err(|e| format!("{}: {}", id, e))?;
+        let limits = match config.limits {
+            Some(ref limits) => parse_limits(limits)
+                .map_err(|e| format!("{}: {}", id, e))?,
+            None => CompileLimits::default(),
+        };
+        let compiler = match config.kind.as_ref() {
             "compiler" => {
                 Box::new(BinaryCompiler::new(
                     config.compiler_file.unwrap(),
-                    shlex::split(&config.compiler_args.as_ref().unwrap())
+                    shlex::split(config.compiler_args.as_ref().unwrap())
                         .unwrap().into_boxed_slice(),
                     config.code_file,
+                    config.source_exts.unwrap_or_default()
+                        .into_boxed_slice(),
                     config.execute_file,
                     shlex::split(&config.execute_args)
                         .unwrap().into_boxed_slice(),
-                )) as Box<Compiler>
+                    limits,
+                ).with_seccomp(seccomp)) as Box<dyn Compiler>
             },
             "interpreter" => {
                 Box::new(Interpreter::new(
@@ -58,9 +121,92 @@ fn parse_compilers_yaml(v: &[u8]) -> HashMap<String, Box<Compiler>> {
                     config.execute_file,
                     shlex::split(&config.execute_args)
                         .unwrap().into_boxed_slice(),
-                )) as Box<Compiler>
+                ).with_seccomp(seccomp)) as Box<dyn Compiler>
             }
             _ => panic!(),
-        })
+        };
+        Ok((id, RegistryEntry { compiler, fingerprint }))
     }).collect()
 }
+
+fn parse_limits(config: &LimitsConfig) -> Result<CompileLimits, String> {
+    let defaults = CompileLimits::default();
+    let time = |time: &Option<Quantity>, default| match *time {
+        Some(ref time) => case::parse_time(time).map_err(quantity_error),
+        None => Ok(default),
+    };
+    let memory = |memory: &Option<Quantity>, default| match *memory {
+        Some(ref memory) => case::parse_memory(memory)
+            .map(|memory| memory as u64).map_err(quantity_error),
+        None => Ok(default),
+    };
+    Ok(CompileLimits {
+        cpu_time: time(&config.cpu_time, defaults.cpu_time)?,
+        wall_time: time(&config.wall_time, defaults.wall_time)?,
+        memory: memory(&config.memory, defaults.memory)?,
+        pids: config.pids.unwrap_or(defaults.pids),
+        output: memory(&config.output, defaults.output)?,
+    })
+}
+
+fn quantity_error(error: CaseError) -> String {
+    match error {
+        CaseError::ParseError(message) => message,
+        error => format!("{:?}", error),
+    }
+}
+
+fn parse_seccomp(config: Option<&SeccompConfig>) -> Result<Profile, String> {
+    match config {
+        None => Ok(Profile::default()),
+        Some(&SeccompConfig { allow: Some(ref allow), deny: None }) =>
+            Profile::allow(allow),
+        Some(&SeccompConfig { allow: None, deny: Some(ref deny) }) =>
+            Profile::deny(deny),
+        Some(_) => Err(String::from(
+            "seccomp needs exactly one of allow and deny")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_errors() {
+        let config = |limits: &str| format!("c:
+    type: compiler
+    compiler_file: /usr/bin/gcc
+    compiler_args: gcc foo.c
+    code_file: foo.c
+    execute_file: foo
+    execute_args: foo
+    limits: {}
+", limits);
+        assert!(parse_compilers_yaml(
+            config("{cpu_time: 10s, memory: 256m}").as_bytes()).is_ok());
+        let error = parse_compilers_yaml(
+            config("{cpu_time: fast}").as_bytes()).err().unwrap();
+        assert_eq!(error, "c: invalid time: fast");
+        assert!(parse_compilers_yaml(
+            config("{output: -1}").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn seccomp_errors() {
+        let config = |seccomp: &str| format!("sh:
+    type: interpreter
+    code_file: foo.sh
+    execute_file: /bin/sh
+    execute_args: sh foo.sh
+    seccomp: {}
+", seccomp);
+        assert!(parse_compilers_yaml(
+            config("{deny: [ptrace]}").as_bytes()).is_ok());
+        let error = parse_compilers_yaml(
+            config("{deny: [fly]}").as_bytes()).err().unwrap();
+        assert_eq!(error, "sh: unknown syscall fly");
+        assert!(parse_compilers_yaml(
+            config("{allow: [read], deny: [ptrace]}").as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,912 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use libc;
+use case::{CaseConfig, CaseError, CaseResult, CaseVec, Policy, ProblemKind,
+           Scoring};
+use cgroup::{self, CGroup};
+use compile::{CompileError, Compiler, Files, Source, Target};
+use config::Registry;
+use sandbox::{ExecuteOptions, ExecuteResult, Limit, Limits, Pipe, Port, Sandbox,
+              SandboxError};
+use util::Pool;
+use verdict::{ExitStatus, Status, Verdict};
+
+const WALL_TIME_SLACK: Duration = Duration::from_secs(1);
+const MAX_PIDS: u64 = 64;
+const MAX_OUTPUT: u64 = 67108864;
+const MAX_OPEN_FILES: u64 = 256;
+const MAX_MESSAGE_LEN: u64 = 4096;
+const SPECIAL_JUDGE_TIME: Duration = Duration::from_secs(10);
+const SPECIAL_JUDGE_MEMORY: u64 = 536870912;
+
+pub type JudgeResult<T> = Result<T, JudgeError>;
+
+#[derive(Debug)]
+pub struct Report {
+    pub verdicts: Box<[Verdict]>,
+    // Scores of the subtasks, in the order of the problem config.
+    pub subtask_scores: Box<[i32]>,
+    pub score: i32,
+}
+
+#[derive(Debug)]
+pub enum JudgeError {
+    Case(CaseError),
+    Sandbox(SandboxError),
+}
+
+// Builds the submission for the problem. For function problems the source is
+// placed at the code file named by the grader, and built together with the
+// grader files. Compile errors are in the inner result.
+pub fn build<R: Read + Seek>(
+    compiler: &dyn Compiler,
+    language: &str,
+    source: Source,
+    cases: &mut CaseVec<R>,
+    pool: &Pool<Sandbox>,
+) -> CaseResult<Result<Target, CompileError>> {
+    if cases.kind() != ProblemKind::Function {
+        return Ok(compiler.compile(&source, &Files::new(), pool));
+    }
+    let code_file = match cases.grader(language) {
+        Some(grader) => grader.code_file().to_path_buf(),
+        None => return Ok(Err(CompileError::InvalidSource(
+            format!("language {} is not supported by this problem", language)))),
+    };
+    let extras = cases.read_grader_files(language)?;
+    let source = match source {
+        Source::Single(code) => {
+            let mut files = Files::new();
+            if let Err(e) = files.insert(code_file, code) {
+                return Ok(Err(e));
+            }
+            Source::Files(files)
+        },
+        source => source,
+    };
+    Ok(compiler.compile(&source, &extras, pool))
+}
+
+// The output is checked by the special judge if given, which runs in a
+// sandbox of its own, or by the checker of the problem otherwise. The policy
+// of the problem is used unless one is given.
+pub fn judge<R: Read + Seek>(
+    target: &Target,
+    special_judge: Option<&Target>,
+    policy: Option<Policy>,
+    cases: &mut CaseVec<R>,
+    pool: &Pool<Sandbox>,
+) -> JudgeResult<Report> {
+    judge_subtasks(cases, policy, |cases, index| {
+        let (mut sandbox, mut judge_sandbox) = match special_judge {
+            Some(_) => {
+                let (sandbox, judge_sandbox) = Sandbox::get_two(pool)?;
+                (sandbox, Some(judge_sandbox))
+            },
+            None => (Sandbox::get_one(pool)?, None),
+        };
+        let verdict = judge_case(
+            target, special_judge.zip(judge_sandbox.as_mut()),
+            cases, index, &mut sandbox);
+        // Both go back to the pool before either error is returned.
+        let recycled = sandbox.recycle(pool);
+        let judge_recycled = judge_sandbox
+            .map_or(Ok(()), |judge_sandbox| judge_sandbox.recycle(pool));
+        recycled.and(judge_recycled)?;
+        Ok(verdict?)
+    })
+}
+
+fn judge_case<R: Read + Seek>(
+    target: &Target,
+    special_judge: Option<(&Target, &mut Sandbox)>,
+    cases: &mut CaseVec<R>,
+    index: usize,
+    sandbox: &mut Sandbox,
+) -> CaseResult<Verdict> {
+    let input_path = sandbox.in_dir().join("input");
+    let output_path = sandbox.out_dir().join("output");
+    io::copy(&mut cases.open_input(index)?, &mut File::create(&input_path)?)?;
+    File::create(&output_path)?;
+    let case = cases.get(index).unwrap();
+    let mut cgroup = case_cgroup(case, sandbox)?;
+    let limits = case_limits(case, cases.stack(), cgroup.is_some());
+    let score = case.score();
+    let checker = cases.checker().build();
+    let result = target.execute(
+        sandbox,
+        &[],
+        Box::new([]),
+        Box::new([(PathBuf::from("/in/input"), Port::stdin()),
+                  (PathBuf::from("/out/output"), Port::stdout())]),
+        ExecuteOptions {
+            limits,
+            cgroup: cgroup.as_mut(),
+            ..Default::default()
+        });
+    let mut result = match result {
+        Ok(result) => result,
+        Err(e) => return Ok(
+            Verdict::new(Status::SystemError).with_message(&e.to_string())),
+    };
+    check_memory(&mut result, case.memory() as u64);
+    let exit_status = result.exit_status;
+    let mut verdict = if let Some(limit) = result.limit_exceeded {
+        limit_verdict(limit)
+    } else if !exit_status.success() {
+        failure_verdict(exit_status)
+    } else if let Some((special_judge, judge_sandbox)) = special_judge {
+        run_special_judge(special_judge, judge_sandbox, cases, index,
+                          &output_path, score)?
+    } else if checker.check(&mut File::open(&output_path)?,
+                            &mut cases.open_output(index)?)? {
+        let mut verdict = Verdict::new(Status::Accepted);
+        verdict.score = score;
+        verdict
+    } else {
+        Verdict::new(Status::WrongAnswer)
+    };
+    record_usage(&mut verdict, &result);
+    verdict.exit_status = Some(exit_status);
+    Ok(verdict)
+}
+
+// Builds the interactor of an interactive problem in the language it names.
+pub fn build_interactor<R: Read + Seek>(
+    registry: &Registry,
+    cases: &mut CaseVec<R>,
+    pool: &Pool<Sandbox>,
+) -> CaseResult<Result<Target, CompileError>> {
+    let language = match cases.interactor() {
+        Some(interactor) => interactor.language().to_string(),
+        None => return Err(
+            CaseError::FileNotFound(String::from("interactor"))),
+    };
+    let compiler = registry.get_compiler(&language).ok_or_else(|| {
+        CaseError::ParseError(
+            format!("interactor: unknown language {}", language))
+    })?;
+    let source = cases.read_interactor()?;
+    Ok(compiler.compile(&source, &Files::new(), pool))
+}
+
+// Builds the special judge of the problem in the language it names. With a
+// cache in the registry, each package has it compiled once.
+pub fn build_special_judge<R: Read + Seek>(
+    registry: &Registry,
+    cases: &mut CaseVec<R>,
+    pool: &Pool<Sandbox>,
+) -> CaseResult<Result<Target, CompileError>> {
+    let language = match cases.special_judge() {
+        Some(special_judge) => special_judge.language().to_string(),
+        None => return Err(
+            CaseError::FileNotFound(String::from("special judge"))),
+    };
+    let compiler = registry.get_compiler(&language)
+        .ok_or_else(|| CaseError::ParseError(
+            format!("special_judge: unknown language {}", language)))?;
+    let source = cases.read_special_judge()?;
+    Ok(compiler.compile(&source, &Files::new(), pool))
+}
+
+// Runs the special judge as "special_judge input output answer message".
+fn run_special_judge<R: Read + Seek>(
+    special_judge: &Target,
+    sandbox: &mut Sandbox,
+    cases: &mut CaseVec<R>,
+    index: usize,
+    output_path: &Path,
+    score: i32,
+) -> CaseResult<Verdict> {
+    let in_dir = sandbox.in_dir();
+    let message_path = sandbox.out_dir().join("message");
+    io::copy(&mut cases.open_input(index)?,
+             &mut File::create(in_dir.join("input"))?)?;
+    fs::copy(output_path, in_dir.join("output"))?;
+    io::copy(&mut cases.open_output(index)?,
+             &mut File::create(in_dir.join("answer"))?)?;
+    File::create(&message_path)?;
+    let cgroup_limits = cgroup::Limits {
+        memory: Some(SPECIAL_JUDGE_MEMORY),
+        swap: None,
+        pids: Some(MAX_PIDS),
+    };
+    let mut cgroup =
+        CGroup::with_limits(sandbox.cgroup_root(), &cgroup_limits)?;
+    let result = special_judge.execute(
+        sandbox,
+        &[String::from("/in/input"), String::from("/in/output"),
+          String::from("/in/answer"), String::from("/out/message")],
+        Box::new([]),
+        Box::new([]),
+        ExecuteOptions {
+            limits: Limits {
+                cpu_time: Some(SPECIAL_JUDGE_TIME),
+                wall_time: Some(SPECIAL_JUDGE_TIME * 2 + WALL_TIME_SLACK),
+                processes:
+                    if cgroup.is_some() { None } else { Some(MAX_PIDS) },
+                ..Default::default()
+            },
+            cgroup: cgroup.as_mut(),
+            ..Default::default()
+        });
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => return Ok(Verdict::new(Status::SystemError)
+            .with_message(&format!("special judge: {}", e))),
+    };
+    if let Some(limit) = result.limit_exceeded {
+        return Ok(Verdict::new(Status::SystemError).with_message(
+            &format!("special judge: {:?} limit exceeded", limit)));
+    }
+    let message = read_message(&message_path)?;
+    Ok(testlib_verdict("special judge", result.exit_status, &message, score))
+}
+
+// Judges an interactive problem. The stdout of the submission is the stdin of
+// the interactor and vice versa. The interactor is run as
+// "interactor input verdict answer", and its exit code decides the outcome
+// testlib-style, with the verdict file as the message.
+pub fn judge_interactive<R: Read + Seek>(
+    target: &Target,
+    interactor: &Target,
+    policy: Option<Policy>,
+    cases: &mut CaseVec<R>,
+    pool: &Pool<Sandbox>,
+) -> JudgeResult<Report> {
+    judge_subtasks(cases, policy, |cases, index| {
+        let (mut user_sandbox, mut interactor_sandbox) =
+            Sandbox::get_two(pool)?;
+        let verdict = judge_interactive_case(
+            target, interactor, cases, index,
+            &mut user_sandbox, &mut interactor_sandbox);
+        // Both go back to the pool before either error is returned.
+        let recycled = user_sandbox.recycle(pool);
+        recycled.and(interactor_sandbox.recycle(pool))?;
+        Ok(verdict?)
+    })
+}
+
+// Judges the cases subtask by subtask, skipping the cases as the policy says.
+// Subtasks score nothing if a subtask they depend on did not pass.
+fn judge_subtasks<R, F>(
+    cases: &mut CaseVec<R>,
+    policy: Option<Policy>,
+    mut judge_case: F,
+) -> JudgeResult<Report>
+    where R: Read + Seek,
+          F: FnMut(&mut CaseVec<R>, usize) -> JudgeResult<Verdict> {
+    let policy = policy.unwrap_or_else(|| cases.policy());
+    let mut verdicts = vec![Verdict::new(Status::Skipped); cases.len()];
+    let mut subtask_scores = Vec::with_capacity(cases.subtasks().len());
+    let mut passed = Vec::with_capacity(cases.subtasks().len());
+    let mut stopped = false;
+    for index in 0..cases.subtasks().len() {
+        let (scoring, depends_passed, case_range) = {
+            let subtask = &cases.subtasks()[index];
+            (subtask.scoring(),
+             subtask.depends().iter().all(|&depend| passed[depend]),
+             subtask.cases())
+        };
+        let judged = !stopped && (depends_passed || policy != Policy::Ioi);
+        let mut all_accepted = judged && depends_passed;
+        let mut score = 0;
+        if judged {
+            for case_index in case_range {
+                let verdict = judge_case(cases, case_index)?;
+                let accepted = verdict.status == Status::Accepted;
+                all_accepted &= accepted;
+                score += verdict.score;
+                verdicts[case_index] = verdict;
+                if !accepted && policy == Policy::Acm {
+                    stopped = true;
+                    break;
+                }
+                if !accepted && policy == Policy::Ioi &&
+                    scoring == Scoring::Min {
+                    break;
+                }
+            }
+        }
+        subtask_scores.push(match scoring {
+            _ if !depends_passed => 0,
+            Scoring::Min if all_accepted => cases.subtasks()[index].score(),
+            Scoring::Min => 0,
+            Scoring::Sum => score,
+        });
+        passed.push(all_accepted);
+    }
+    // All or nothing under ACM.
+    if stopped {
+        subtask_scores.iter_mut().for_each(|score| *score = 0);
+    }
+    let score = subtask_scores.iter().sum();
+    Ok(Report {
+        verdicts: verdicts.into_boxed_slice(),
+        subtask_scores: subtask_scores.into_boxed_slice(),
+        score,
+    })
+}
+
+fn judge_interactive_case<R: Read + Seek>(
+    target: &Target,
+    interactor: &Target,
+    cases: &mut CaseVec<R>,
+    index: usize,
+    user_sandbox: &mut Sandbox,
+    interactor_sandbox: &mut Sandbox,
+) -> CaseResult<Verdict> {
+    let in_dir = interactor_sandbox.in_dir();
+    let verdict_path = interactor_sandbox.out_dir().join("verdict");
+    io::copy(&mut cases.open_input(index)?,
+             &mut File::create(in_dir.join("input"))?)?;
+    io::copy(&mut cases.open_output(index)?,
+             &mut File::create(in_dir.join("answer"))?)?;
+    File::create(&verdict_path)?;
+    let case = cases.get(index).unwrap();
+    let mut user_cgroup = case_cgroup(case, user_sandbox)?;
+    let mut interactor_cgroup = case_cgroup(case, interactor_sandbox)?;
+    let user_limits =
+        case_limits(case, cases.stack(), user_cgroup.is_some());
+    let interactor_limits =
+        case_limits(case, cases.stack(), interactor_cgroup.is_some());
+    // The fifos are made before either side starts, so that a side which
+    // failed to start can be stood in for, instead of leaving the other
+    // blocked in opening them.
+    let (user_stdin, interactor_stdout) = Pipe::new();
+    let (user_stdout, interactor_stdin) = Pipe::new();
+    let fifos = [user_sandbox.in_dir().join("stdin"),
+                 user_sandbox.in_dir().join("stdout")];
+    user_stdin.into_fifo(&fifos[0])?;
+    user_stdout.into_fifo(&fifos[1])?;
+    interactor_stdin.into_fifo(&in_dir.join("stdin"))?;
+    interactor_stdout.into_fifo(&in_dir.join("stdout"))?;
+    let stand_in = || {
+        fifos.iter().map(|path| {
+            OpenOptions::new().read(true).write(true).open(path)
+        }).collect::<Vec<_>>()
+    };
+    // Each side opens the fifo written by the submission first.
+    let results = thread::scope(|scope| {
+        let user_thread = scope.spawn(|| {
+            let result = target.execute(
+                user_sandbox,
+                &[],
+                Box::new([]),
+                Box::new([(PathBuf::from("/in/stdout"), Port::stdout()),
+                          (PathBuf::from("/in/stdin"), Port::stdin())]),
+                ExecuteOptions {
+                    limits: user_limits,
+                    cgroup: user_cgroup.as_mut(),
+                    ..Default::default()
+                });
+            let held = if result.is_err() { stand_in() } else { Vec::new() };
+            (result, held)
+        });
+        let result = interactor.execute(
+            interactor_sandbox,
+            &[String::from("/in/input"), String::from("/out/verdict"),
+              String::from("/in/answer")],
+            Box::new([]),
+            Box::new([(PathBuf::from("/in/stdin"), Port::stdin()),
+                      (PathBuf::from("/in/stdout"), Port::stdout())]),
+            ExecuteOptions {
+                limits: interactor_limits,
+                cgroup: interactor_cgroup.as_mut(),
+                ..Default::default()
+            });
+        let held = if result.is_err() { stand_in() } else { Vec::new() };
+        let (user_result, user_held) = user_thread.join().unwrap();
+        drop((held, user_held));
+        (user_result, result)
+    });
+    let (mut user_result, mut interactor_result) = match results {
+        (Ok(user_result), Ok(interactor_result)) =>
+            (user_result, interactor_result),
+        (Err(e), _) | (_, Err(e)) => return Ok(
+            Verdict::new(Status::SystemError).with_message(&e.to_string())),
+    };
+    check_memory(&mut user_result, case.memory() as u64);
+    check_memory(&mut interactor_result, case.memory() as u64);
+    let message = read_message(&verdict_path)?;
+    let mut verdict = interactive_verdict(
+        &user_result, &interactor_result, &message, case.score());
+    record_usage(&mut verdict, &user_result);
+    verdict.exit_status = Some(user_result.exit_status);
+    Ok(verdict)
+}
+
+// Limits of the submission take precedence, and then failures of the
+// interactor.
+fn interactive_verdict(
+    user: &ExecuteResult,
+    interactor: &ExecuteResult,
+    message: &str,
+    score: i32,
+) -> Verdict {
+    if let Some(limit) = user.limit_exceeded {
+        return limit_verdict(limit);
+    }
+    if let Some(limit) = interactor.limit_exceeded {
+        return Verdict::new(Status::SystemError)
+            .with_message(&format!("interactor: {:?} limit exceeded", limit));
+    }
+    let exit_status = match interactor.exit_status {
+        // The submission quit without reading everything.
+        ExitStatus::Signaled(signal) if signal == libc::SIGPIPE =>
+            ExitStatus::Exited(1),
+        exit_status => exit_status,
+    };
+    match exit_status {
+        ExitStatus::Exited(0) | ExitStatus::Exited(1) |
+        ExitStatus::Exited(2) | ExitStatus::Exited(7) => (),
+        _ => return testlib_verdict("interactor", exit_status, message, score),
+    }
+    if !user.exit_status.success() {
+        return failure_verdict(user.exit_status);
+    }
+    testlib_verdict("interactor", exit_status, message, score)
+}
+
+// The seccomp filter kills the submission with SIGSYS on a denied call.
+fn failure_verdict(exit_status: ExitStatus) -> Verdict {
+    let status = match exit_status {
+        ExitStatus::Signaled(signal) if signal == libc::SIGSYS =>
+            Status::RestrictedFunction,
+        _ => Status::RuntimeError,
+    };
+    Verdict::new(status).with_message(&exit_status.to_string())
+}
+
+// Exit codes follow testlib: 0 accepts, 1 and 2 reject, 3 reports a failure of
+// the program itself, and 7 awards the fraction of the score leading the
+// message, which may follow "points".
+fn testlib_verdict(
+    program: &str,
+    exit_status: ExitStatus,
+    message: &str,
+    score: i32,
+) -> Verdict {
+    let verdict = match exit_status {
+        ExitStatus::Exited(0) => {
+            let mut verdict = Verdict::new(Status::Accepted);
+            verdict.score = score;
+            verdict
+        },
+        ExitStatus::Exited(1) | ExitStatus::Exited(2) =>
+            Verdict::new(Status::WrongAnswer),
+        ExitStatus::Exited(7) => {
+            let mut words = message.split_whitespace().peekable();
+            if words.peek() == Some(&"points") {
+                words.next();
+            }
+            let fraction = words.next()
+                .and_then(|word| word.parse::<f64>().ok())
+                .filter(|fraction| (0. ..=1.).contains(fraction));
+            match fraction {
+                Some(fraction) => {
+                    let mut verdict = Verdict::new(Status::WrongAnswer);
+                    verdict.score =
+                        (f64::from(score) * fraction).round() as i32;
+                    verdict
+                },
+                None => return Verdict::new(Status::SystemError).with_message(
+                    &format!("{}: invalid points: {}", program, message)),
+            }
+        },
+        _ => return Verdict::new(Status::SystemError).with_message(
+            &format!("{} {}: {}", program, exit_status, message)),
+    };
+    verdict.with_message(message)
+}
+
+fn read_message(path: &Path) -> io::Result<String> {
+    let mut message = Vec::new();
+    File::open(path)?.take(MAX_MESSAGE_LEN).read_to_end(&mut message)?;
+    Ok(String::from_utf8_lossy(&message).trim().to_string())
+}
+
+// The process limit is a fallback for the pids limit of the cgroup.
+fn case_limits(case: &CaseConfig, stack: Option<u64>, cgroup: bool)
+    -> Limits {
+    Limits {
+        cpu_time: Some(case.time()),
+        wall_time: Some(case.time() * 2 + WALL_TIME_SLACK),
+        file_size: Some(MAX_OUTPUT),
+        stack,
+        open_files: Some(MAX_OPEN_FILES),
+        processes: if cgroup { None } else { Some(MAX_PIDS) },
+    }
+}
+
+fn case_cgroup(case: &CaseConfig, sandbox: &Sandbox)
+    -> io::Result<Option<CGroup>> {
+    CGroup::with_limits(sandbox.cgroup_root(), &cgroup::Limits {
+        memory: Some(case.memory() as u64),
+        swap: None,
+        pids: Some(MAX_PIDS),
+    })
+}
+
+// Without a cgroup nothing stops the program at the memory limit, so its peak
+// is checked once it is done.
+fn check_memory(result: &mut ExecuteResult, memory: u64) {
+    if result.usage.is_none() && result.limit_exceeded.is_none() &&
+        result.memory > memory {
+        result.limit_exceeded = Some(Limit::Memory);
+    }
+}
+
+// Takes the usage of the whole cgroup if there is one, or else of the
+// program alone.
+fn record_usage(verdict: &mut Verdict, result: &ExecuteResult) {
+    let (time, memory) = match result.usage {
+        Some(ref usage) => (usage.cpu_time, usage.memory_peak),
+        None => (result.cpu_time, result.memory),
+    };
+    verdict.time = time;
+    verdict.memory = memory as usize;
+}
+
+fn limit_verdict(limit: Limit) -> Verdict {
+    Verdict::new(match limit {
+        Limit::Memory => Status::MemoryLimitExceeded,
+        Limit::Output => Status::OutputLimitExceeded,
+        _ => Status::TimeLimitExceeded,
+    })
+}
+
+impl From<CaseError> for JudgeError {
+    fn from(e: CaseError) -> JudgeError {
+        JudgeError::Case(e)
+    }
+}
+
+impl From<SandboxError> for JudgeError {
+    fn from(e: SandboxError) -> JudgeError {
+        JudgeError::Sandbox(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use cache::Cache;
+    use compile::{Files, Source};
+    use config::Registry;
+    use tempdir::TempDir;
+
+    fn judge_aplusb(source: &[u8]) -> Box<[Verdict]> {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let gcc = Registry::builtin().get_compiler("c").unwrap();
+        let target = gcc.compile(
+            &Source::Single(source.to_vec()), &Files::new(), &pool).unwrap();
+        let data = include_bytes!("testdata/aplusb.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        judge(&target, None, None, &mut cases, &pool).unwrap()
+            .verdicts
+    }
+
+    #[test]
+    fn aplusb_accepted() {
+        let verdicts = judge_aplusb(br#"#include <stdio.h>
+int main(void) {
+    int a, b;
+    scanf("%d%d", &a, &b);
+    printf("%d\n", a + b);
+}"#);
+        assert_eq!(verdicts.len(), 10);
+        assert!(verdicts.iter().all(|v| v.status == Status::Accepted));
+        assert_eq!(verdicts.iter().map(|v| v.score).sum::<i32>(), 110);
+        assert!(verdicts.iter().all(|v| v.time > Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn aplusb_wrong_answer() {
+        let verdicts = judge_aplusb(br#"#include <stdio.h>
+int main(void) {
+    int a, b;
+    scanf("%d%d", &a, &b);
+    printf("%d\n", a - b);
+    return b == 0;
+}"#);
+        assert!(verdicts.iter().all(|v| v.status == Status::WrongAnswer));
+        assert_eq!(verdicts.iter().map(|v| v.score).sum::<i32>(), 0);
+    }
+
+    #[test]
+    fn aplusb_time_limit_exceeded() {
+        let verdicts = judge_aplusb(br#"#include <stdio.h>
+int main(void) {
+    int a, b;
+    scanf("%d%d", &a, &b);
+    for (; a == 14548; );
+    printf("%d\n", a + b);
+}"#);
+        assert_eq!(verdicts[7].status, Status::Accepted);
+        assert_eq!(verdicts[8].status, Status::TimeLimitExceeded);
+        assert!(verdicts[8].time >= Duration::from_millis(500));
+        assert_eq!(verdicts[8].score, 0);
+    }
+
+    #[test]
+    fn aplusb_memory_limit_exceeded() {
+        let verdicts = judge_aplusb(br#"#include <stdio.h>
+#include <stdlib.h>
+#define SIZE (96 << 20)
+int main(void) {
+    int a, b, i;
+    volatile char *data = malloc(SIZE);
+    for (i = 0; i < SIZE; i += 4096) {
+        data[i] = 1;
+    }
+    scanf("%d%d", &a, &b);
+    printf("%d\n", a + b);
+}"#);
+        assert_eq!(verdicts[0].status, Status::MemoryLimitExceeded);
+        assert_eq!(verdicts[0].score, 0);
+        assert_eq!(verdicts[9].status, Status::Accepted);
+        assert!(verdicts[9].memory >= 96 << 20);
+    }
+
+    #[test]
+    fn aplusb_compile_error() {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let gcc = Registry::builtin().get_compiler("c").unwrap();
+        let verdict = match gcc.compile(
+            &Source::Single(b"int main(void) { return x; }".to_vec()),
+            &Files::new(),
+            &pool) {
+            Ok(_) => panic!("compiled"),
+            Err(e) => e.verdict(),
+        };
+        assert_eq!(verdict.status, Status::CompileError);
+        assert!(verdict.message.contains("undeclared"));
+        assert!(!verdict.exit_status.unwrap().success());
+        drop(pool.get_one().unwrap());
+    }
+
+    #[test]
+    fn aplusb_grader() {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let gcc = Registry::builtin().get_compiler("c").unwrap();
+        let mut extras = Files::new();
+        extras.insert(PathBuf::from("add.h"),
+                      b"int add(int a, int b);\n".to_vec()).unwrap();
+        extras.insert(PathBuf::from("grader.c"), br#"#include <stdio.h>
+#include "add.h"
+int main(void) {
+    int a, b;
+    scanf("%d%d", &a, &b);
+    printf("%d\n", add(a, b));
+}"#.to_vec()).unwrap();
+        let source = Source::Single(
+            b"#include \"add.h\"\nint add(int a, int b) { return a + b; }\n"
+                .to_vec());
+        let target = gcc.compile(&source, &extras, &pool).unwrap();
+        let data = include_bytes!("testdata/aplusb.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        let verdicts =
+            judge(&target, None, None, &mut cases, &pool).unwrap().verdicts;
+        assert!(verdicts.iter().all(|v| v.status == Status::Accepted));
+    }
+
+    #[test]
+    fn aplusb_function() {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let data = include_bytes!("testdata/aplusb-function.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        let registry = Registry::builtin();
+        let source = Source::Single(
+            b"#include \"add.h\"\nint add(int a, int b) { return a + b; }\n"
+                .to_vec());
+        let target = build(&registry.get_compiler("cc").unwrap(), "cc",
+                           source.clone(), &mut cases, &pool).unwrap().unwrap();
+        let verdicts =
+            judge(&target, None, None, &mut cases, &pool).unwrap().verdicts;
+        assert!(verdicts.iter().all(|v| v.status == Status::Accepted));
+        let error = match build(&registry.get_compiler("py").unwrap(), "py",
+                                source, &mut cases, &pool).unwrap() {
+            Ok(_) => panic!("compiled"),
+            Err(e) => e,
+        };
+        assert_eq!(error.verdict().status, Status::CompileError);
+    }
+
+    #[test]
+    fn aplusb_runtime_error() {
+        let verdicts = judge_aplusb(b"int main(void) { return 1; }");
+        assert!(verdicts.iter().all(|v| v.status == Status::RuntimeError));
+    }
+
+    #[test]
+    fn aplusb_restricted_function() {
+        let verdicts = judge_aplusb(
+            b"#include <sys/ptrace.h>\n\
+              int main(void) { ptrace(PTRACE_TRACEME, 0, 0, 0); }");
+        assert!(verdicts.iter()
+            .all(|v| v.status == Status::RestrictedFunction));
+    }
+
+    fn judge_guess(source: &[u8]) -> Box<[Verdict]> {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        pool.put(Sandbox::new().unwrap());
+        let data = include_bytes!("testdata/guess.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        let registry = Registry::builtin();
+        let target = build(&registry.get_compiler("c").unwrap(), "c",
+                           Source::Single(source.to_vec()), &mut cases, &pool)
+            .unwrap().unwrap();
+        let interactor =
+            build_interactor(registry, &mut cases, &pool).unwrap().unwrap();
+        judge_interactive(&target, &interactor, None, &mut cases, &pool)
+            .unwrap().verdicts
+    }
+
+    #[test]
+    fn guess_accepted() {
+        let verdicts = judge_guess(br#"#include <stdio.h>
+#include <string.h>
+int main(void) {
+    int low = 1, high = 1000000000;
+    char reply[16];
+    for (;;) {
+        int guess = low + (high - low) / 2;
+        printf("%d\n", guess);
+        fflush(stdout);
+        scanf("%15s", reply);
+        if (strcmp(reply, "higher") == 0) {
+            low = guess + 1;
+        } else if (strcmp(reply, "lower") == 0) {
+            high = guess - 1;
+        } else {
+            return 0;
+        }
+    }
+}"#);
+        assert_eq!(verdicts.len(), 4);
+        assert!(verdicts.iter().all(|v| v.status == Status::Accepted));
+        assert_eq!(verdicts.iter().map(|v| v.score).sum::<i32>(), 100);
+        assert!(verdicts[0].message.starts_with("found in "));
+    }
+
+    #[test]
+    fn guess_wrong_answer() {
+        let verdicts = judge_guess(br#"#include <stdio.h>
+int main(void) {
+    for (int i = 0; i < 32; ++i) {
+        printf("2\n");
+        fflush(stdout);
+    }
+}"#);
+        assert!(verdicts.iter().all(|v| v.status == Status::WrongAnswer));
+        assert_eq!(verdicts[0].message, "too many guesses");
+        assert_eq!(verdicts.iter().map(|v| v.score).sum::<i32>(), 0);
+    }
+
+    #[test]
+    fn guess_runtime_error() {
+        let verdicts = judge_guess(b"int main(void) { return 1; }");
+        assert!(verdicts.iter().all(|v| v.status == Status::RuntimeError));
+    }
+
+    #[test]
+    fn guess_time_limit_exceeded() {
+        let verdicts = judge_guess(b"int main(void) { for (;;); }");
+        assert!(verdicts.iter().all(
+            |v| v.status == Status::TimeLimitExceeded));
+    }
+
+    fn judge_special(source: &[u8], registry: &Registry) -> Box<[Verdict]> {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        pool.put(Sandbox::new().unwrap());
+        let data = include_bytes!("testdata/aplusb-special.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        let target = build(&registry.get_compiler("c").unwrap(), "c",
+                           Source::Single(source.to_vec()), &mut cases, &pool)
+            .unwrap().unwrap();
+        let special_judge = build_special_judge(
+            registry, &mut cases, &pool).unwrap().unwrap();
+        judge(&target, Some(&special_judge), None, &mut cases, &pool).unwrap()
+            .verdicts
+    }
+
+    #[test]
+    fn aplusb_special_judge() {
+        let dir = TempDir::new("jd-cache").unwrap();
+        let registry = Registry::builtin_with_cache(
+            Cache::new(dir.path().to_path_buf(), 1048576).unwrap());
+        let verdicts = judge_special(br#"#include <stdio.h>
+int main(void) {
+    int a, b;
+    scanf("%d%d", &a, &b);
+    printf("%d\n", a + b + (a % 2 != 0));
+}"#, &registry);
+        assert!(registry.cache().unwrap().size() > 0);
+        for verdict in verdicts.iter() {
+            if verdict.status == Status::Accepted {
+                assert!(verdict.message.starts_with("ok "));
+            } else {
+                assert_eq!(verdict.status, Status::WrongAnswer);
+                assert_eq!(verdict.message, "points 0.5 off by one");
+                assert!(verdict.score == 5 || verdict.score == 10);
+            }
+        }
+        assert!(verdicts.iter().any(|v| v.status == Status::Accepted));
+        assert!(verdicts.iter().any(|v| v.status == Status::WrongAnswer));
+        let verdicts = judge_special(
+            b"#include <stdio.h>\nint main(void) { printf(\"x\\n\"); }",
+            &registry);
+        assert!(verdicts.iter().all(|v| v.status == Status::WrongAnswer));
+        assert_eq!(verdicts[0].message, "no number found");
+    }
+
+    #[test]
+    fn aplusb_subtasks() {
+        let pool = Pool::new();
+        pool.put(Sandbox::new().unwrap());
+        let gcc = Registry::builtin().get_compiler("c").unwrap();
+        let target = gcc.compile(&Source::Single(br#"#include <stdio.h>
+int main(void) {
+    int a, b;
+    scanf("%d%d", &a, &b);
+    printf("%d\n", a + b + (a == 23107 || a == 17574));
+}"#.to_vec()), &Files::new(), &pool).unwrap();
+        let data = include_bytes!("testdata/aplusb-subtasks.zip");
+        let mut cases = CaseVec::load(Cursor::new(&data[..])).unwrap();
+        let mut judge_policy = |policy| {
+            let report =
+                judge(&target, None, policy, &mut cases, &pool).unwrap();
+            let statuses = report.verdicts.iter()
+                .map(|v| v.status.short_name()).collect::<Vec<_>>();
+            (statuses, report.subtask_scores, report.score)
+        };
+        let (statuses, subtask_scores, score) = judge_policy(None);
+        assert_eq!(statuses, ["AC", "AC", "AC", "AC", "WA", "SKIP",
+                              "AC", "WA", "AC", "SKIP"]);
+        assert_eq!(&subtask_scores[..], [30, 0, 20, 0]);
+        assert_eq!(score, 50);
+        let (statuses, subtask_scores, score) = judge_policy(Some(Policy::Oi));
+        assert_eq!(statuses, ["AC", "AC", "AC", "AC", "WA", "AC",
+                              "AC", "WA", "AC", "AC"]);
+        assert_eq!(&subtask_scores[..], [30, 0, 20, 0]);
+        assert_eq!(score, 50);
+        let (statuses, subtask_scores, score) =
+            judge_policy(Some(Policy::Acm));
+        assert_eq!(statuses, ["AC", "AC", "AC", "AC", "WA", "SKIP",
+                              "SKIP", "SKIP", "SKIP", "SKIP"]);
+        assert_eq!(&subtask_scores[..], [0, 0, 0, 0]);
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn memory_without_cgroup() {
+        let mut result = ExecuteResult {
+            exit_status: ExitStatus::Exited(0),
+            cpu_time: Duration::from_millis(1),
+            wall_time: Duration::from_millis(1),
+            limit_exceeded: None,
+            memory: 2048,
+            usage: None,
+            leftover_processes: 0,
+        };
+        check_memory(&mut result, 2048);
+        assert_eq!(result.limit_exceeded, None);
+        check_memory(&mut result, 1024);
+        assert_eq!(result.limit_exceeded, Some(Limit::Memory));
+        result.limit_exceeded = None;
+        result.usage = Some(Default::default());
+        check_memory(&mut result, 1024);
+        assert_eq!(result.limit_exceeded, None);
+    }
+}
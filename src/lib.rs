@@ -1,6 +1,8 @@
 extern crate bincode;
+extern crate hex;
 #[macro_use]
 extern crate lazy_static;
+extern crate libc;
 extern crate linear_map;
 extern crate nix;
 extern crate rand;
@@ -8,13 +10,19 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_yaml;
+extern crate sha2;
 extern crate shlex;
 extern crate tempdir;
 extern crate zip;
 
+pub mod cache;
 pub mod case;
 pub mod cgroup;
+pub mod check;
 pub mod compile;
 pub mod config;
+pub mod judge;
 pub mod sandbox;
+pub mod seccomp;
 pub mod util;
+pub mod verdict;
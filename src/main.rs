@@ -1,40 +1,60 @@
 extern crate jd4_5;
 
-use jd4_5::compile;
+use std::env;
+use std::fs::{self, File};
+use std::process;
+use jd4_5::cache::Cache;
+use jd4_5::case::{CaseVec, ProblemKind};
+use jd4_5::compile::Source;
 use jd4_5::config::Registry;
+use jd4_5::judge;
 use jd4_5::sandbox::Sandbox;
 use jd4_5::util::Pool;
 
-pub fn main() {
-    let pool = Pool::new();
-    pool.put(Sandbox::new());
-    pool.put(Sandbox::new());
-    let gcc = Registry::builtin().get_compiler("c").unwrap();
-
-    let user_source = br#"#include <stdio.h>
+const CACHE_SIZE: u64 = 268435456;
 
-int main(void) {
-    printf("42\n");
-}"#;
-    let judge_source = br#"#define _POSIX_C_SOURCE 1
-#include <stdio.h>
-
-int main(void) {
-    FILE *fp = fdopen(3, "r");
-    if (!fp) {
-        printf("open error\n");
-        return 1;
+// Usage: jd4_5 PACKAGE LANGUAGE SOURCE
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: {} PACKAGE LANGUAGE SOURCE", args[0]);
+        process::exit(2);
     }
-    int a;
-    if (fscanf(fp, "%d", &a) != 1) {
-        printf("read error\n");
-        return 1;
+    let pool = Pool::new();
+    pool.put(Sandbox::new().unwrap());
+    pool.put(Sandbox::new().unwrap());
+    let cache = Cache::new(env::temp_dir().join("jd4_5-cache"), CACHE_SIZE)
+        .unwrap();
+    let registry = Registry::builtin_with_cache(cache);
+    let compiler = registry.get_compiler(&args[2]).unwrap_or_else(|| {
+        eprintln!("unknown language {}", args[2]);
+        process::exit(2);
+    });
+    let mut cases = CaseVec::load(File::open(&args[1]).unwrap()).unwrap();
+    let source = Source::Single(fs::read(&args[3]).unwrap());
+    let target = match judge::build(
+        &compiler, &args[2], source, &mut cases, &pool).unwrap() {
+        Ok(target) => target,
+        Err(e) => {
+            let verdict = e.verdict();
+            println!("{} {}", verdict.status.short_name(), verdict.message);
+            return;
+        },
+    };
+    let report = if cases.kind() == ProblemKind::Interactive {
+        let interactor = judge::build_interactor(&registry, &mut cases, &pool)
+            .unwrap().unwrap();
+        judge::judge_interactive(&target, &interactor, None, &mut cases, &pool)
+    } else if cases.special_judge().is_some() {
+        let special_judge = judge::build_special_judge(
+            &registry, &mut cases, &pool).unwrap().unwrap();
+        judge::judge(&target, Some(&special_judge), None, &mut cases, &pool)
+    } else {
+        judge::judge(&target, None, None, &mut cases, &pool)
+    }.unwrap();
+    for (index, verdict) in report.verdicts.iter().enumerate() {
+        println!("#{} {} {} {:?} {} {}", index, verdict.status.short_name(),
+                 verdict.score, verdict.time, verdict.memory, verdict.message);
     }
-    printf("a = %d\n", a);
-    return 0;
-}"#;
-    // These two can be parallelized...
-    let user_target = gcc.compile(user_source, &pool);
-    let judge_target = gcc.compile(judge_source, &pool);
-    compile::run(user_target, judge_target, &pool);
+    println!("subtasks {:?} score {}", report.subtask_scores, report.score);
 }
@@ -1,34 +1,86 @@
 use std::env;
+use std::error;
 use std::ffi::CString;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::Shutdown;
 use std::os::unix;
-use std::os::unix::net::UnixStream;
-use std::os::unix::io::{FromRawFd, RawFd};
-use std::path::{Path, PathBuf};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::ptr;
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use bincode;
-use nix::fcntl::{self, OFlag};
+use libc;
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
 use nix::mount::{self, MntFlags, MsFlags};
+use nix::poll::{self, EventFlags, PollFd};
 use nix::sched::{self, CloneFlags};
-use nix::sys::socket;
+use nix::sys::signal::{self, SigmaskHow, SigSet, Signal};
+use nix::sys::socket::{self, sockopt};
 use nix::sys::stat::{self, Mode, SFlag};
-use nix::sys::wait::{self, WaitStatus};
-use nix::unistd::{self, Uid, Gid};
+use nix::unistd::{self, Pid, Uid, Gid};
+use serde_yaml;
+use cgroup::{CGroup, Root, Usage};
+use seccomp::Profile;
 use tempdir::TempDir;
-use util;
+use util::{self, Pool};
+use verdict::ExitStatus;
 
 pub struct Sandbox {
     stream: UnixStream,
     dir: TempDir,
+    pid: Pid,
+    config: SandboxConfig,
+    cgroup_root: Root,
 }
 
-pub type ExecuteResult = Result<i32, ExecuteError>;
+// What the sandbox looks like from the inside. The system directories are
+// always bound read-only, and the binds here come on top of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub binds: Box<[Bind]>,
+    // Bytes and inodes of /tmp.
+    #[serde(default = "default_tmpfs_size")]
+    pub tmpfs_size: u64,
+    #[serde(default = "default_tmpfs_inodes")]
+    pub tmpfs_inodes: u64,
+    // Files directly in /dev on the host, bound to the same paths.
+    #[serde(default = "default_devices")]
+    pub devices: Box<[PathBuf]>,
+    #[serde(default = "default_name")]
+    pub hostname: String,
+    // The user in /etc/passwd.
+    #[serde(default = "default_name")]
+    pub guest_name: String,
+    #[serde(default = "default_guest_id")]
+    pub guest_uid: u32,
+    #[serde(default = "default_guest_id")]
+    pub guest_gid: u32,
+    // Where cgroups are made, relative to the cgroup hierarchy, e.g. a
+    // subtree delegated to the judge.
+    #[serde(default = "default_cgroup_root")]
+    pub cgroup_root: PathBuf,
+}
+
+pub type SandboxResult<T> = Result<T, SandboxError>;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum ExecuteError {
-    Signaled(i32),
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SandboxError {
+    Setup(String),
+    // Failures while running a command, after the sandbox was set up.
+    Runtime(String),
+    Exec(String),
+    Ipc(String),
+    ChildDied,
 }
 
 pub struct Pipe(Arc<PipeState>);
@@ -40,9 +92,49 @@ pub struct PipeState {
 
 pub struct Port(String, RawFd, OFlag);
 
+// The size limits are applied as resource limits, which also work on hosts
+// without cgroup delegation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub cpu_time: Option<Duration>,
+    pub wall_time: Option<Duration>,
+    // Bytes of any file written.
+    pub file_size: Option<u64>,
+    // Bytes, or UNLIMITED.
+    pub stack: Option<u64>,
+    pub open_files: Option<u64>,
+    // Counts every process of the guest user, so only set without a cgroup
+    // to limit the pids.
+    pub processes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    CpuTime,
+    WallTime,
+    Memory,
+    // Set by the sandbox when the file size limit is hit, or by callers which
+    // capture output.
+    Output,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ExecuteResult {
+    pub exit_status: ExitStatus,
+    pub cpu_time: Duration,
+    pub wall_time: Duration,
+    pub limit_exceeded: Option<Limit>,
+    // Peak resident set size of the program in bytes, for when there is no
+    // cgroup to account the whole group.
+    pub memory: u64,
+    pub usage: Option<Usage>,
+    // Processes still running after the program exited, which were killed.
+    pub leftover_processes: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 enum Request {
-    Execute(ExecuteCommand),
+    Execute(Box<ExecuteCommand>),
     Cleanup,
 }
 
@@ -53,87 +145,312 @@ struct ExecuteCommand {
     envs: Box<[String]>,
     working_dir: PathBuf,
     open_files: Box<[(PathBuf, RawFd, i32)]>,
-    cgroup_file: Option<PathBuf>,
+    limits: Limits,
+    seccomp: Option<Profile>,
+    cgroup_socket: Option<PathBuf>,
 }
 
-struct Bind {
-    source: PathBuf,
-    target: PathBuf,
-    mode: AccessMode,
+// The source is an absolute path on the host, and the target a path relative
+// to the root of the sandbox.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Bind {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub mode: AccessMode,
 }
 
-enum AccessMode {
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessMode {
     ReadOnly,
     ReadWrite,
 }
 
+// Lifts a resource limit, such as the stack size.
+pub const UNLIMITED: u64 = libc::RLIM_INFINITY;
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(10);
+// In /in while a command starts.
+pub const CGROUP_SOCKET_NAME: &str = "cgroup";
+
 pub fn default_envs() -> Box<[String]> {
     Box::new([String::from("PATH=/usr/bin:/bin"), String::from("HOME=/")])
 }
 
+// How a command is run, besides where its files are. All but the cgroup are
+// sent along in the ExecuteCommand, while the cgroup stays on this side.
+pub struct ExecuteOptions<'a> {
+    pub envs: Box<[String]>,
+    pub limits: Limits,
+    pub seccomp: Option<Profile>,
+    pub cgroup: Option<&'a mut CGroup>,
+}
+
+impl<'a> Default for ExecuteOptions<'a> {
+    fn default() -> ExecuteOptions<'a> {
+        ExecuteOptions {
+            envs: default_envs(),
+            limits: Limits::default(),
+            seccomp: None,
+            cgroup: None,
+        }
+    }
+}
+
 impl Sandbox {
-    // TODO(iceboy): close existing fds
-    pub fn new() -> Sandbox {
+    pub fn new() -> SandboxResult<Sandbox> {
+        Sandbox::with_config(SandboxConfig::builtin())
+    }
+
+    pub fn with_config(config: &SandboxConfig) -> SandboxResult<Sandbox> {
+        config.validate()?;
         let (parent_fd, child_fd) = socket::socketpair(
             socket::AddressFamily::Unix,
             socket::SockType::Stream,
             None,
-            socket::SockFlag::empty()).unwrap();
-        let sandbox_dir = TempDir::new("jd-sandbox").unwrap();
+            socket::SockFlag::empty()).map_err(setup_error("socketpair"))?;
+        let stream = unsafe { UnixStream::from_raw_fd(parent_fd) };
+        let child_stream = unsafe { UnixStream::from_raw_fd(child_fd) };
+        let sandbox_dir = TempDir::new("jd-sandbox")
+            .map_err(setup_error("create sandbox dir"))?;
         let in_dir = sandbox_dir.path().join("in");
-        fs::create_dir(&in_dir).unwrap();
+        fs::create_dir(&in_dir).map_err(setup_error("create in dir"))?;
         let out_dir = sandbox_dir.path().join("out");
-        fs::create_dir(&out_dir).unwrap();
+        fs::create_dir(&out_dir).map_err(setup_error("create out dir"))?;
         let mount_dir = sandbox_dir.path().join("mount");
-        fs::create_dir(&mount_dir).unwrap();
+        fs::create_dir(&mount_dir).map_err(setup_error("create mount dir"))?;
         let mut binds = Bind::defaults().into_vec();
+        binds.extend_from_slice(&config.binds);
         binds.push(
-            Bind::new(in_dir, PathBuf::from("in"), AccessMode::ReadOnly));
+            Bind::new(in_dir, PathBuf::from("in"), AccessMode::ReadOnly)?);
         binds.push(
-            Bind::new(out_dir, PathBuf::from("out"), AccessMode::ReadWrite));
-        match unistd::fork().unwrap() {
-            unistd::ForkResult::Parent { .. } => {
-                unistd::close(child_fd).unwrap();
-            },
+            Bind::new(out_dir, PathBuf::from("out"), AccessMode::ReadWrite)?);
+        let pid = match unistd::fork().map_err(setup_error("fork"))? {
+            unistd::ForkResult::Parent { child } => child,
             unistd::ForkResult::Child => {
-                unistd::close(parent_fd).unwrap();
-                do_child(&mount_dir, &binds, child_fd);
+                drop(stream);
+                do_child(&mount_dir, &binds, config, child_stream);
             },
         };
-        Sandbox {
-            stream: unsafe { UnixStream::from_raw_fd(parent_fd) },
+        drop(child_stream);
+        let mut sandbox = Sandbox {
+            stream,
             dir: sandbox_dir,
-        }
+            pid,
+            config: config.clone(),
+            cgroup_root: Root::detect(&config.cgroup_root),
+        };
+        // The child reports whether the mounts are in place before serving
+        // any request.
+        let ready: SandboxResult<()> =
+            bincode::deserialize_from(&mut sandbox.stream)?;
+        ready.map(|()| sandbox)
     }
 
     pub fn in_dir(&self) -> PathBuf { self.dir.path().join("in") }
     pub fn out_dir(&self) -> PathBuf { self.dir.path().join("out") }
+    pub fn cgroup_root(&self) -> &Root { &self.cgroup_root }
 
     pub fn execute(
         &mut self,
         file: PathBuf,
         args: Box<[String]>,
-        envs: Box<[String]>,
         working_dir: PathBuf,
         pipes: Box<[(Pipe, Port)]>,
-        cgroup_file: Option<PathBuf>,
-    ) -> ExecuteResult {
-        let open_files = pipes.into_vec().into_iter().map(
-            |(pipe, Port(name, fd, oflag))| {
-                pipe.into_fifo(&self.in_dir().join(&name));
-                (PathBuf::from("/in").join(&name), fd, oflag.bits())
-            }).collect::<Vec<_>>().into_boxed_slice();
-        let request = Request::Execute(ExecuteCommand {
-            file, args, envs, working_dir, open_files, cgroup_file });
-        bincode::serialize_into(&mut self.stream, &request).unwrap();
-        bincode::deserialize_from(&mut self.stream).unwrap()
+        files: Box<[(PathBuf, Port)]>,
+        options: ExecuteOptions,
+    ) -> SandboxResult<ExecuteResult> {
+        let ExecuteOptions { envs, limits, seccomp, mut cgroup } = options;
+        let mut open_files = Vec::with_capacity(pipes.len() + files.len());
+        for (pipe, Port(name, fd, oflag)) in pipes.into_vec() {
+            pipe.into_fifo(&self.in_dir().join(&name))
+                .map_err(runtime_error("mkfifo"))?;
+            open_files.push(
+                (PathBuf::from("/in").join(&name), fd, oflag.bits()));
+        }
+        open_files.extend(files.into_vec().into_iter().map(
+            |(path, Port(_, fd, oflag))| (path, fd, oflag.bits())));
+        let open_files = open_files.into_boxed_slice();
+        let cgroup_path = self.in_dir().join(CGROUP_SOCKET_NAME);
+        let cgroup_listener = match cgroup {
+            Some(_) => Some(UnixListener::bind(&cgroup_path)
+                .map_err(runtime_error("bind cgroup socket"))?),
+            None => None,
+        };
+        let cgroup_socket = cgroup.as_ref()
+            .map(|_| PathBuf::from("/in").join(CGROUP_SOCKET_NAME));
+        let request = Request::Execute(Box::new(ExecuteCommand {
+            file,
+            args,
+            envs,
+            working_dir,
+            open_files,
+            limits,
+            seccomp,
+            cgroup_socket,
+        }));
+        bincode::serialize_into(&mut self.stream, &request)?;
+        let attached = match (cgroup_listener, cgroup.as_mut()) {
+            (Some(listener), Some(cgroup)) => {
+                let attached = self.attach_cgroup(&listener, cgroup);
+                let _ = fs::remove_file(&cgroup_path);
+                attached
+            },
+            _ => Ok(()),
+        };
+        let attached = match (attached, cgroup.as_mut(), limits.cpu_time) {
+            (Ok(()), Some(cgroup), Some(limit)) =>
+                self.watch_cgroup(cgroup, limit),
+            (attached, _, _) => attached,
+        };
+        // Always read the response to keep the stream in sync, even if the
+        // attach failed and the child was left waiting.
+        let result: SandboxResult<ExecuteResult> =
+            bincode::deserialize_from(&mut self.stream)?;
+        attached?;
+        let mut result = result?;
+        if let Some(cgroup) = cgroup {
+            let usage = cgroup.usage().map_err(runtime_error("cgroup usage"))?;
+            if result.limit_exceeded.is_none() {
+                if limits.cpu_time.is_some_and(|limit| usage.cpu_time > limit) {
+                    result.limit_exceeded = Some(Limit::CpuTime);
+                } else if usage.oom_kills > 0 ||
+                    (result.exit_status == ExitStatus::Signaled(libc::SIGKILL) &&
+                     usage.memory_failcnt > 0) {
+                    result.limit_exceeded = Some(Limit::Memory);
+                }
+            }
+            result.usage = Some(usage);
+        }
+        Ok(result)
+    }
+
+    // Cleans up and puts the sandbox back to the pool. A sandbox which fails
+    // to clean up is replaced by a new one. If that fails too, the sandbox is
+    // lost, and the pool fails from then on rather than blocking.
+    pub fn recycle(mut self, pool: &Pool<Sandbox>) -> SandboxResult<()> {
+        if self.cleanup().is_ok() {
+            pool.put(self);
+            return Ok(());
+        }
+        let config = self.config.clone();
+        drop(self);
+        match Sandbox::with_config(&config) {
+            Ok(sandbox) => {
+                pool.put(sandbox);
+                Ok(())
+            },
+            Err(e) => {
+                pool.lose();
+                Err(e)
+            },
+        }
+    }
+
+    pub fn get_one(pool: &Pool<Sandbox>) -> SandboxResult<Sandbox> {
+        pool.get_one().ok_or_else(lost_error)
+    }
+
+    pub fn get_two(pool: &Pool<Sandbox>) -> SandboxResult<(Sandbox, Sandbox)> {
+        pool.get_two().ok_or_else(lost_error)
     }
 
-    pub fn cleanup(&mut self) {
-        bincode::serialize_into(&mut self.stream, &Request::Cleanup).unwrap();
-        util::clean_dir(&self.in_dir());
-        util::clean_dir(&self.out_dir());
-        bincode::deserialize_from(&mut self.stream).unwrap()
+    pub fn cleanup(&mut self) -> SandboxResult<()> {
+        bincode::serialize_into(&mut self.stream, &Request::Cleanup)?;
+        let cleaned = util::clean_dir(&self.in_dir())
+            .and_then(|()| util::clean_dir(&self.out_dir()))
+            .map_err(setup_error("clean sandbox dir"));
+        let result: SandboxResult<()> =
+            bincode::deserialize_from(&mut self.stream)?;
+        cleaned.and(result)
+    }
+
+    // The child connects before execve and waits for our reply. The peer
+    // credentials carry its pid in our PID namespace. If the child fails
+    // before connecting, the response arrives on the stream instead.
+    fn attach_cgroup(
+        &self,
+        listener: &UnixListener,
+        cgroup: &mut CGroup,
+    ) -> SandboxResult<()> {
+        let mut fds = [PollFd::new(listener.as_raw_fd(), EventFlags::POLLIN),
+                       PollFd::new(self.stream.as_raw_fd(), EventFlags::POLLIN)];
+        poll::poll(&mut fds, -1).map_err(|e| SandboxError::Ipc(e.to_string()))?;
+        if !fds[0].revents().is_some_and(|events| events.contains(EventFlags::POLLIN)) {
+            return Ok(());
+        }
+        let (mut stream, _) = listener.accept()
+            .map_err(runtime_error("accept cgroup socket"))?;
+        let credentials = socket::getsockopt(
+            stream.as_raw_fd(), sockopt::PeerCredentials)
+            .map_err(runtime_error("peer credentials"))?;
+        cgroup.add_task(Pid::from_raw(credentials.pid()))
+            .map_err(runtime_error("attach cgroup"))?;
+        stream.write_all(&[0]).map_err(runtime_error("attach cgroup"))
+    }
+
+    // Waits for the response while watching the CPU time of the whole group,
+    // as the child only sees the time of the process it started. The group
+    // is killed once over the limit.
+    fn watch_cgroup(
+        &self,
+        cgroup: &mut CGroup,
+        limit: Duration,
+    ) -> SandboxResult<()> {
+        let timeout = WATCHDOG_INTERVAL.as_millis() as libc::c_int;
+        loop {
+            let mut fds =
+                [PollFd::new(self.stream.as_raw_fd(), EventFlags::POLLIN)];
+            match poll::poll(&mut fds, timeout) {
+                Ok(0) | Err(::nix::Error::Sys(Errno::EINTR)) => (),
+                Ok(_) => return Ok(()),
+                Err(e) => return Err(SandboxError::Ipc(e.to_string())),
+            }
+            let usage = cgroup.usage().map_err(runtime_error("cgroup usage"))?;
+            if usage.cpu_time > limit {
+                return cgroup.kill_all().map_err(runtime_error("kill cgroup"));
+            }
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        // The child exits once its end of the stream is closed.
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let _ = wait_pid(self.pid);
+    }
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SandboxError::Setup(ref message) =>
+                write!(f, "sandbox setup failed: {}", message),
+            SandboxError::Runtime(ref message) =>
+                write!(f, "sandbox runtime failed: {}", message),
+            SandboxError::Exec(ref message) =>
+                write!(f, "exec failed: {}", message),
+            SandboxError::Ipc(ref message) =>
+                write!(f, "sandbox ipc failed: {}", message),
+            SandboxError::ChildDied => write!(f, "sandbox child died"),
+        }
+    }
+}
+
+impl error::Error for SandboxError {}
+
+impl From<bincode::Error> for SandboxError {
+    fn from(e: bincode::Error) -> SandboxError {
+        if let bincode::ErrorKind::Io(ref e) = *e {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof |
+                io::ErrorKind::BrokenPipe |
+                io::ErrorKind::ConnectionReset => return SandboxError::ChildDied,
+                _ => (),
+            }
+        }
+        SandboxError::Ipc(e.to_string())
     }
 }
 
@@ -146,34 +463,34 @@ impl Pipe {
         (Pipe(state.clone()), Pipe(state))
     }
 
-    pub fn into_fifo(self, path: &Path) {
+    pub fn into_fifo(self, path: &Path) -> io::Result<()> {
         let mut maybe_path = self.0.path.lock().unwrap();
         match maybe_path.clone() {
-            Some(existing_path) => {
-                fs::hard_link(existing_path, path).unwrap();
-            },
+            Some(existing_path) => fs::hard_link(existing_path, path),
             None => {
-                unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+                unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+                    .map_err(io::Error::other)?;
                 *maybe_path = Some(path.to_path_buf());
                 self.0.condvar.notify_all();
+                Ok(())
             },
         }
     }
 
-    pub fn into_reader(self) -> File {
+    pub fn into_reader(self) -> io::Result<File> {
         let mut maybe_path = self.0.path.lock().unwrap();
         while maybe_path.is_none() {
             maybe_path = self.0.condvar.wait(maybe_path).unwrap();
         }
-        File::open(maybe_path.as_ref().unwrap()).unwrap()
+        File::open(maybe_path.as_ref().unwrap())
     }
 
-    pub fn into_writer(self) -> File {
+    pub fn into_writer(self) -> io::Result<File> {
         let mut maybe_path = self.0.path.lock().unwrap();
         while maybe_path.is_none() {
             maybe_path = self.0.condvar.wait(maybe_path).unwrap();
         }
-        File::create(maybe_path.as_ref().unwrap()).unwrap()
+        File::create(maybe_path.as_ref().unwrap())
     }
 }
 
@@ -193,21 +510,102 @@ impl Port {
     pub fn extra() -> Port {
         Port(String::from("extra"), 3, OFlag::O_RDONLY)
     }
+
+    // The ports above. Their names are taken in /in when piped.
+    pub fn all() -> [Port; 4] {
+        [Port::stdin(), Port::stdout(), Port::stderr(), Port::extra()]
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl SandboxConfig {
+    pub fn builtin() -> &'static SandboxConfig {
+        lazy_static! {
+            static ref BUILTIN_CONFIG: SandboxConfig =
+                serde_yaml::from_slice(include_bytes!("data/sandbox.yaml"))
+                    .unwrap();
+        }
+        &BUILTIN_CONFIG
+    }
+
+    pub fn load<R: Read>(reader: R) -> SandboxResult<SandboxConfig> {
+        let config: SandboxConfig = serde_yaml::from_reader(reader)
+            .map_err(setup_error("parse sandbox config"))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> SandboxResult<()> {
+        let error = |message: String| {
+            SandboxError::Setup(format!("invalid sandbox config: {}", message))
+        };
+        for bind in self.binds.iter() {
+            bind.validate()?;
+        }
+        for device in self.devices.iter() {
+            let name = device.strip_prefix("/dev").map(Path::new);
+            if !name.is_ok_and(|name| {
+                is_inner_path(name) && name.components().count() == 1
+            }) {
+                return Err(error(format!("device {}", device.display())));
+            }
+        }
+        if self.guest_name.is_empty() ||
+            self.guest_name.contains(&[':', '\n'][..]) {
+            return Err(error(format!("guest name {}", self.guest_name)));
+        }
+        if !is_inner_path(&self.cgroup_root) {
+            return Err(error(format!(
+                "cgroup root {}", self.cgroup_root.display())));
+        }
+        Ok(())
+    }
+}
+
+impl Default for SandboxConfig {
+    fn default() -> SandboxConfig {
+        SandboxConfig {
+            binds: Box::new([]),
+            tmpfs_size: default_tmpfs_size(),
+            tmpfs_inodes: default_tmpfs_inodes(),
+            devices: default_devices(),
+            hostname: default_name(),
+            guest_name: default_name(),
+            guest_uid: default_guest_id(),
+            guest_gid: default_guest_id(),
+            cgroup_root: default_cgroup_root(),
+        }
+    }
 }
 
 impl Bind {
-    fn new(source: PathBuf, target: PathBuf, mode: AccessMode) -> Bind {
-        assert!(source.is_absolute());
-        assert!(target.is_relative());
-        Bind { source, target, mode }
+    pub fn new(source: PathBuf, target: PathBuf, mode: AccessMode)
+        -> SandboxResult<Bind> {
+        let bind = Bind { source, target, mode };
+        bind.validate()?;
+        Ok(bind)
+    }
+
+    // The source is absolute, and the target stays inside the sandbox.
+    fn validate(&self) -> SandboxResult<()> {
+        if !self.source.is_absolute() || !is_inner_path(&self.target) {
+            return Err(SandboxError::Setup(format!(
+                "invalid bind {} to {}",
+                self.source.display(), self.target.display())));
+        }
+        Ok(())
     }
 
     fn defaults() -> Box<[Bind]> {
         fn ro(source: &str, target: &str) -> Bind {
-            Bind::new(
-                PathBuf::from(source),
-                PathBuf::from(target),
-                AccessMode::ReadOnly)
+            Bind {
+                source: PathBuf::from(source),
+                target: PathBuf::from(target),
+                mode: AccessMode::ReadOnly,
+            }
         }
         Box::new([
             ro("/bin", "bin"),
@@ -220,47 +618,123 @@ impl Bind {
             ro("/usr/lib64", "usr/lib64"),
             ro("/usr/libexec", "usr/libexec"),
             ro("/usr/share", "usr/share"),
-            ro("/var/lib/ghc", "var/lib/ghc"),
         ])
     }
 }
 
-fn do_child(mount_dir: &Path, binds: &[Bind], child_fd: RawFd) -> ! {
-    init_sandbox(mount_dir, binds);
-    let mut stream = unsafe { UnixStream::from_raw_fd(child_fd) };
+fn default_tmpfs_size() -> u64 {
+    16777216
+}
+
+fn default_tmpfs_inodes() -> u64 {
+    4096
+}
+
+fn default_devices() -> Box<[PathBuf]> {
+    Box::new([PathBuf::from("/dev/null"), PathBuf::from("/dev/urandom")])
+}
+
+fn default_name() -> String {
+    String::from("icebox")
+}
+
+fn default_guest_id() -> u32 {
+    1000
+}
+
+fn default_cgroup_root() -> PathBuf {
+    PathBuf::from("sandbox")
+}
+
+// A relative path which stays below where it is resolved from.
+fn is_inner_path(path: &Path) -> bool {
+    path.components().next().is_some() &&
+        path.components().all(|component| {
+            matches!(component, Component::Normal(_))
+        })
+}
+
+fn lost_error() -> SandboxError {
+    SandboxError::Setup(String::from("a sandbox of the pool was lost"))
+}
+
+fn setup_error<E: fmt::Display>(
+    context: &'static str,
+) -> impl FnOnce(E) -> SandboxError {
+    move |e| SandboxError::Setup(format!("{}: {}", context, e))
+}
+
+fn runtime_error<E: fmt::Display>(
+    context: &'static str,
+) -> impl FnOnce(E) -> SandboxError {
+    move |e| SandboxError::Runtime(format!("{}: {}", context, e))
+}
+
+fn do_child(
+    mount_dir: &Path,
+    binds: &[Bind],
+    config: &SandboxConfig,
+    mut stream: UnixStream,
+) -> ! {
+    let socket_fd = stream.as_raw_fd();
+    // Nothing the daemon has open may leak into the sandbox.
+    let ready = close_fds(&[socket_fd])
+        .and_then(|()| reopen_std_fds())
+        .map_err(setup_error("close fds"))
+        .and_then(|()| init_sandbox(mount_dir, binds, config, socket_fd));
+    if bincode::serialize_into(&mut stream, &ready).is_err() || ready.is_err() {
+        process::exit(1);
+    }
     loop {
-        match bincode::deserialize_from(&mut stream) {
+        let sent = match bincode::deserialize_from(&mut stream) {
             Ok(Request::Execute(command)) => bincode::serialize_into(
-                &mut stream, &do_execute(child_fd, command)).unwrap(),
+                &mut stream, &do_execute(*command)),
             Ok(Request::Cleanup) => bincode::serialize_into(
-                &mut stream, &do_cleanup()).unwrap(),
+                &mut stream, &do_cleanup()),
             Err(_) => process::exit(0),
         };
+        if sent.is_err() {
+            process::exit(1);
+        }
     }
 }
 
-fn init_sandbox(mount_dir: &Path, binds: &[Bind]) {
+fn init_sandbox(
+    mount_dir: &Path,
+    binds: &[Bind],
+    config: &SandboxConfig,
+    socket_fd: RawFd,
+) -> SandboxResult<()> {
     let host_uid = unistd::geteuid();
     let host_gid = unistd::getegid();
-    let guest_uid = Uid::from_raw(1000);
-    let guest_gid = Gid::from_raw(1000);
+    let guest_uid = Uid::from_raw(config.guest_uid);
+    let guest_gid = Gid::from_raw(config.guest_gid);
     sched::unshare(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWUTS |
                    CloneFlags::CLONE_NEWIPC | CloneFlags::CLONE_NEWUSER |
-                   CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNET).unwrap();
-    write_file("/proc/self/uid_map", &format!("{} {} 1", guest_uid, host_uid));
-    write_file("/proc/self/setgroups", "deny");
-    write_file("/proc/self/gid_map", &format!("{} {} 1", guest_gid, host_gid));
-    unistd::setresuid(guest_uid, guest_uid, guest_uid).unwrap();
-    unistd::setresgid(guest_gid, guest_gid, guest_gid).unwrap();
-    unistd::sethostname("icebox").unwrap();
-    // TODO(iceboy): Reap zombies?
-    match unistd::fork().unwrap() {
+                   CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNET)
+        .map_err(setup_error("unshare"))?;
+    write_file("/proc/self/uid_map", &format!("{} {} 1", guest_uid, host_uid))
+        .map_err(setup_error("write uid_map"))?;
+    write_file("/proc/self/setgroups", "deny")
+        .map_err(setup_error("write setgroups"))?;
+    write_file("/proc/self/gid_map", &format!("{} {} 1", guest_gid, host_gid))
+        .map_err(setup_error("write gid_map"))?;
+    unistd::setresuid(guest_uid, guest_uid, guest_uid)
+        .map_err(setup_error("setresuid"))?;
+    unistd::setresgid(guest_gid, guest_gid, guest_gid)
+        .map_err(setup_error("setresgid"))?;
+    unistd::sethostname(&config.hostname)
+        .map_err(setup_error("sethostname"))?;
+    // The child is the init process of the PID namespace, which reaps orphans
+    // after each execute.
+    match unistd::fork().map_err(setup_error("fork"))? {
         unistd::ForkResult::Parent { child } => {
-            match wait::waitpid(child, None).unwrap() {
-                WaitStatus::Exited(_, status) => {
-                    process::exit(status as i32)
-                },
-                e => panic!("{:?}", e),
+            // Only the child talks to the daemon, so it notices when the
+            // child dies.
+            let _ = unistd::close(socket_fd);
+            match wait_pid(child) {
+                Ok(ExitStatus::Exited(status)) => process::exit(status),
+                _ => process::exit(1),
             }
         },
         unistd::ForkResult::Child => (),
@@ -269,159 +743,898 @@ fn init_sandbox(mount_dir: &Path, binds: &[Bind]) {
                  mount_dir,
                  Some("tmpfs"),
                  MsFlags::MS_NOSUID,
-                 None as Option<&[u8]>).unwrap();
-    env::set_current_dir(mount_dir).unwrap();
-    fs::create_dir("proc").unwrap();
+                 None as Option<&[u8]>).map_err(setup_error("mount root"))?;
+    env::set_current_dir(mount_dir).map_err(setup_error("chdir root"))?;
+    fs::create_dir("proc").map_err(setup_error("create /proc"))?;
     mount::mount(Some("sandbox_proc"),
                  "proc",
                  Some("proc"),
                  MsFlags::MS_NOSUID,
-                 None as Option<&[u8]>).unwrap();
-    fs::create_dir("dev").unwrap();
-    bind_dev("/dev/null", "dev/null");
-    bind_dev("/dev/urandom", "dev/urandom");
-    fs::create_dir("tmp").unwrap();
+                 None as Option<&[u8]>).map_err(setup_error("mount /proc"))?;
+    fs::create_dir("dev").map_err(setup_error("create /dev"))?;
+    for device in config.devices.iter() {
+        bind_dev(device)?;
+    }
+    fs::create_dir("tmp").map_err(setup_error("create /tmp"))?;
     mount::mount(Some("sandbox_tmp"),
                  "tmp",
                  Some("tmpfs"),
                  MsFlags::MS_NOSUID,
-                 Some("size=16m,nr_inodes=4k")).unwrap();
-    binds.iter().for_each(bind_or_link);
-    write_file("etc/passwd", "icebox:x:1000:1000:icebox:/:/bin/bash\n");
-    fs::create_dir("old_root").unwrap();
-    unistd::pivot_root(".", "old_root").unwrap();
-    mount::umount2("old_root", MntFlags::MNT_DETACH).unwrap();
-    fs::remove_dir("old_root").unwrap();
+                 Some(format!("size={},nr_inodes={}", config.tmpfs_size,
+                              config.tmpfs_inodes).as_str()))
+        .map_err(setup_error("mount /tmp"))?;
+    for bind in binds {
+        bind_or_link(bind)?;
+    }
+    write_file("etc/passwd", &format!("{0}:x:{1}:{2}:{0}:/:/bin/bash\n",
+                                      config.guest_name, guest_uid, guest_gid))
+        .map_err(setup_error("write /etc/passwd"))?;
+    fs::create_dir("old_root").map_err(setup_error("create old root"))?;
+    unistd::pivot_root(".", "old_root").map_err(setup_error("pivot_root"))?;
+    mount::umount2("old_root", MntFlags::MNT_DETACH)
+        .map_err(setup_error("umount old root"))?;
+    fs::remove_dir("old_root").map_err(setup_error("remove old root"))?;
     mount::mount(Some("/"),
                  "/",
                  None as Option<&[u8]>,
                  MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY |
                  MsFlags::MS_REC | MsFlags::MS_NOSUID,
-                 None as Option<&[u8]>).unwrap();
+                 None as Option<&[u8]>).map_err(setup_error("remount root"))
 }
 
-fn write_file(path: &str, data: &str) {
-    File::create(path).unwrap().write_all(data.as_bytes()).unwrap();
+fn write_file(path: &str, data: &str) -> io::Result<()> {
+    File::create(path)?.write_all(data.as_bytes())
 }
 
-fn bind_dev(source: &str, target: &str) {
-    stat::mknod(
-        target, SFlag::empty(), Mode::S_IRUSR | Mode::S_IWUSR, 0).unwrap();
+fn bind_dev(source: &Path) -> SandboxResult<()> {
+    let error = |e: ::nix::Error| {
+        SandboxError::Setup(format!("bind {}: {}", source.display(), e))
+    };
+    let target = source.strip_prefix("/").unwrap();
+    stat::mknod(target, SFlag::empty(), Mode::S_IRUSR | Mode::S_IWUSR, 0)
+        .map_err(error)?;
     mount::mount(Some(source),
                  target,
                  None as Option<&[u8]>,
                  MsFlags::MS_BIND | MsFlags::MS_NOSUID,
-                 None as Option<&[u8]>).unwrap();
+                 None as Option<&[u8]>).map_err(error)
 }
 
-fn bind_or_link(bind: &Bind) {
+fn bind_or_link(bind: &Bind) -> SandboxResult<()> {
+    let error = |e: &dyn fmt::Display| {
+        SandboxError::Setup(format!("bind {}: {}", bind.source.display(), e))
+    };
     let file_type = match fs::symlink_metadata(&bind.source) {
         Ok(attr) => attr.file_type(),
-        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
-        Err(e) => panic!("{:?}", e),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(error(&e)),
     };
     if file_type.is_dir() {
-        fs::create_dir_all(&bind.target).unwrap();
+        fs::create_dir_all(&bind.target).map_err(|e| error(&e))?;
         mount::mount(Some(&bind.source),
                      &bind.target,
                      None as Option<&[u8]>,
                      MsFlags::MS_BIND | MsFlags::MS_REC | MsFlags::MS_NOSUID,
-                     None as Option<&[u8]>).unwrap();
-        match bind.mode {
-            AccessMode::ReadOnly => mount::mount(
-                Some(&bind.source),
-                &bind.target,
-                None as Option<&[u8]>,
-                MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY |
-                MsFlags::MS_REC | MsFlags::MS_NOSUID,
-                None as Option<&[u8]>).unwrap(),
-            _ => (),
+                     None as Option<&[u8]>).map_err(|e| error(&e))?;
+        if let AccessMode::ReadOnly = bind.mode {
+            mount::mount(Some(&bind.source),
+                         &bind.target,
+                         None as Option<&[u8]>,
+                         MsFlags::MS_BIND | MsFlags::MS_REMOUNT |
+                         MsFlags::MS_RDONLY | MsFlags::MS_REC |
+                         MsFlags::MS_NOSUID,
+                         None as Option<&[u8]>).map_err(|e| error(&e))?;
         }
     } else if file_type.is_symlink() {
-        let link = fs::read_link(&bind.source).unwrap();
-        unix::fs::symlink(link, &bind.target).unwrap();
+        let link = fs::read_link(&bind.source).map_err(|e| error(&e))?;
+        unix::fs::symlink(link, &bind.target).map_err(|e| error(&e))?;
     }
+    Ok(())
 }
 
-fn do_execute(socket_fd: RawFd, command: ExecuteCommand) -> ExecuteResult {
-    // TODO(iceboy): Reap zombies?
-    match unistd::fork().unwrap() {
+fn do_execute(command: ExecuteCommand) -> SandboxResult<ExecuteResult> {
+    let mut sigchld = SigSet::empty();
+    sigchld.add(Signal::SIGCHLD);
+    signal::sigprocmask(SigmaskHow::SIG_BLOCK, Some(&sigchld), None)
+        .map_err(setup_error("sigprocmask"))?;
+    // The child reports failures before execve through this pipe, which is
+    // closed on a successful execve.
+    let (error_reader, error_writer) = unistd::pipe2(OFlag::O_CLOEXEC)
+        .map_err(setup_error("pipe2"))?;
+    let mut error_reader = unsafe { File::from_raw_fd(error_reader) };
+    let error_writer = unsafe { File::from_raw_fd(error_writer) };
+    match unistd::fork().map_err(setup_error("fork"))? {
         unistd::ForkResult::Parent { child } => {
-            match wait::waitpid(child, None).unwrap() {
-                WaitStatus::Exited(_, status) => Ok(status as i32),
-                WaitStatus::Signaled(_, signal, _) =>
-                    Err(ExecuteError::Signaled(signal as i32)),
-                e => panic!("{:?}", e),
+            drop(error_writer);
+            let mut error = Vec::new();
+            let read = error_reader.read_to_end(&mut error);
+            if read.is_err() || !error.is_empty() {
+                let _ = kill_leftovers();
+                return Err(bincode::deserialize(&error).unwrap_or_else(
+                    |_| SandboxError::Exec(String::from("child failed"))));
             }
+            let result = wait_child(child, &command.limits, &sigchld);
+            let leftover_processes = kill_leftovers()?;
+            result.map(|result| ExecuteResult { leftover_processes, ..result })
         },
         unistd::ForkResult::Child => {
-            unistd::close(socket_fd).unwrap();
-            env::set_current_dir(&command.working_dir).unwrap();
-            for &(ref path, ofd, oflag) in command.open_files.iter() {
-                let fd = fcntl::open(path,
-                                     OFlag::from_bits(oflag).unwrap(),
-                                     stat::Mode::empty()).unwrap();
-                if fd != ofd {
-                    unistd::dup2(fd, ofd).unwrap();
-                    unistd::close(fd).unwrap();
-                }
+            drop(error_reader);
+            // Out of the way of the fds to open, which would replace it.
+            let min_fd = command.open_files.iter()
+                .map(|&(_, fd, _)| fd + 1).max().unwrap_or(0);
+            let moved = fcntl::fcntl(
+                error_writer.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(min_fd));
+            let error_writer = match moved {
+                Ok(fd) => unsafe { File::from_raw_fd(fd) },
+                Err(e) => {
+                    let error = setup_error("dup error pipe")(e);
+                    let _ = bincode::serialize_into(error_writer, &error);
+                    unsafe { libc::_exit(127) }
+                },
+            };
+            let error_fd = error_writer.as_raw_fd();
+            if let Err(error) = exec_command(error_fd, &command) {
+                let _ = bincode::serialize_into(error_writer, &error);
             }
-            let file = CString::new(
-                command.file.as_os_str().to_str().unwrap()).unwrap();
-            let args: Vec<_> = command.args.into_iter()
-                .map(|arg| CString::new(arg.as_str()).unwrap())
-                .collect();
-            let envs: Vec<_> = command.envs.iter()
-                .map(|arg| CString::new(arg.as_str()).unwrap())
-                .collect();
-            unistd::execve(&file, &args, &envs).unwrap();
-            panic!();
+            unsafe { libc::_exit(127) }
         },
     }
 }
 
-fn do_cleanup() {
-    util::clean_dir(Path::new("/tmp"));
+// Only returns on failure.
+fn exec_command(error_fd: RawFd, command: &ExecuteCommand) -> SandboxResult<()> {
+    if let Some(ref cgroup_socket) = command.cgroup_socket {
+        let mut stream = UnixStream::connect(cgroup_socket)
+            .map_err(runtime_error("connect cgroup socket"))?;
+        stream.read_exact(&mut [0]).map_err(runtime_error("attach cgroup"))?;
+    }
+    signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None)
+        .map_err(setup_error("sigprocmask"))?;
+    env::set_current_dir(&command.working_dir).map_err(|e| {
+        SandboxError::Setup(
+            format!("chdir {}: {}", command.working_dir.display(), e))
+    })?;
+    for &(ref path, ofd, oflag) in command.open_files.iter() {
+        let error = |e: &dyn fmt::Display| {
+            SandboxError::Setup(format!("open {}: {}", path.display(), e))
+        };
+        let oflag = OFlag::from_bits(oflag)
+            .ok_or_else(|| error(&"invalid flags"))?;
+        let fd = fcntl::open(path, oflag, stat::Mode::empty())
+            .map_err(|e| error(&e))?;
+        if fd != ofd {
+            unistd::dup2(fd, ofd).map_err(|e| error(&e))?;
+            unistd::close(fd).map_err(|e| error(&e))?;
+        }
+    }
+    // Everything else is closed, including the socket to the daemon. The
+    // standard fds not redirected stay on /dev/null.
+    let mut keep: Vec<RawFd> =
+        command.open_files.iter().map(|&(_, fd, _)| fd).collect();
+    keep.extend_from_slice(&[0, 1, 2, error_fd]);
+    close_fds(&keep).map_err(setup_error("close fds"))?;
+    let error = |e: &dyn fmt::Display| {
+        SandboxError::Exec(format!("{}: {}", command.file.display(), e))
+    };
+    let file = CString::new(command.file.as_os_str().as_bytes())
+        .map_err(|e| error(&e))?;
+    let args = command.args.iter()
+        .map(|arg| CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| error(&e))?;
+    let envs = command.envs.iter()
+        .map(|env| CString::new(env.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| error(&e))?;
+    // As late as possible, so that the limits only apply to the program.
+    set_rlimits(&command.limits)?;
+    if let Some(ref seccomp) = command.seccomp {
+        seccomp.install().map_err(setup_error("seccomp"))?;
+    }
+    match unistd::execve(&file, &args, &envs) {
+        Ok(void) => match void {},
+        Err(e) => Err(error(&e)),
+    }
+}
+
+// Waits for the child while watching its time usage. When a limit is hit,
+// every process in the sandbox is killed, which works since we are the init
+// process of the PID namespace.
+fn wait_child(
+    child: Pid,
+    limits: &Limits,
+    sigchld: &SigSet,
+) -> SandboxResult<ExecuteResult> {
+    let start = Instant::now();
+    let child_pid: libc::pid_t = child.into();
+    let mut limit_exceeded = None;
+    loop {
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { mem::zeroed() };
+        let pid = unsafe {
+            libc::wait4(child_pid, &mut status, libc::WNOHANG, &mut usage)
+        };
+        if pid == child_pid {
+            let exit_status = exit_status(status);
+            let cpu_time = timeval_to_duration(usage.ru_utime) +
+                timeval_to_duration(usage.ru_stime);
+            if exit_status == ExitStatus::Signaled(libc::SIGXCPU) ||
+                limits.cpu_time.is_some_and(|limit| cpu_time > limit) {
+                limit_exceeded = limit_exceeded.or(Some(Limit::CpuTime));
+            } else if exit_status == ExitStatus::Signaled(libc::SIGXFSZ) {
+                limit_exceeded = limit_exceeded.or(Some(Limit::Output));
+            }
+            return Ok(ExecuteResult {
+                exit_status,
+                cpu_time,
+                wall_time: start.elapsed(),
+                limit_exceeded,
+                memory: usage.ru_maxrss as u64 * 1024,
+                usage: None,
+                leftover_processes: 0,
+            });
+        }
+        if pid != 0 {
+            return Err(runtime_error("wait4")(io::Error::last_os_error()));
+        }
+        if limit_exceeded.is_none() {
+            if limits.wall_time.is_some_and(|limit| start.elapsed() > limit) {
+                limit_exceeded = Some(Limit::WallTime);
+            } else if limits.cpu_time.is_some_and(
+                |limit| process_cpu_time(child) > limit) {
+                limit_exceeded = Some(Limit::CpuTime);
+            }
+            if limit_exceeded.is_some() {
+                signal::kill(Pid::from_raw(-1), Signal::SIGKILL)
+                    .map_err(runtime_error("kill"))?;
+            }
+        }
+        let timeout = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: WATCHDOG_INTERVAL.subsec_nanos() as libc::c_long,
+        };
+        unsafe {
+            libc::sigtimedwait(sigchld.as_ref(), ptr::null_mut(), &timeout);
+        }
+    }
+}
+
+// As the init process of the PID namespace, we inherit every orphan. Reaps
+// them, and kills whatever is still running. Returns how many were killed.
+fn kill_leftovers() -> SandboxResult<usize> {
+    reap(libc::WNOHANG);
+    let leftovers = fs::read_dir("/proc").map_err(runtime_error("read /proc"))?
+        .filter_map(|entry| {
+            entry.ok()?.file_name().to_str()?.parse::<libc::pid_t>().ok()
+        })
+        .filter(|&pid| pid != 1)
+        .count();
+    if leftovers > 0 {
+        signal::kill(Pid::from_raw(-1), Signal::SIGKILL)
+            .map_err(runtime_error("kill"))?;
+        reap(0);
+    }
+    Ok(leftovers)
+}
+
+// Reaps children until none is left, or with WNOHANG, until none has exited.
+fn reap(flags: libc::c_int) {
+    while unsafe { libc::waitpid(-1, ptr::null_mut(), flags) } > 0 {}
+}
+
+fn wait_pid(pid: Pid) -> io::Result<ExitStatus> {
+    let mut status = 0;
+    while unsafe { libc::waitpid(pid.into(), &mut status, 0) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(exit_status(status))
+}
+
+fn exit_status(status: libc::c_int) -> ExitStatus {
+    if libc::WIFSIGNALED(status) {
+        ExitStatus::Signaled(libc::WTERMSIG(status))
+    } else {
+        ExitStatus::Exited(libc::WEXITSTATUS(status))
+    }
+}
+
+fn process_cpu_time(pid: Pid) -> Duration {
+    unsafe {
+        let mut clock = 0;
+        let mut time: libc::timespec = mem::zeroed();
+        if libc::clock_getcpuclockid(pid.into(), &mut clock) != 0 ||
+            libc::clock_gettime(clock, &mut time) != 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    }
+}
+
+fn timeval_to_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+fn set_rlimits(limits: &Limits) -> SandboxResult<()> {
+    if let Some(cpu_time) = limits.cpu_time {
+        // Backstop for the watchdog, which checks at a finer grain.
+        let soft = cpu_time.as_secs() + 2;
+        set_rlimit(libc::RLIMIT_CPU, soft, soft + 1)?;
+    }
+    let limits = [(libc::RLIMIT_FSIZE, limits.file_size),
+                  (libc::RLIMIT_STACK, limits.stack),
+                  (libc::RLIMIT_NOFILE, limits.open_files),
+                  (libc::RLIMIT_NPROC, limits.processes)];
+    for &(resource, limit) in limits.iter() {
+        if let Some(limit) = limit {
+            set_rlimit(resource, limit, limit)?;
+        }
+    }
+    set_rlimit(libc::RLIMIT_CORE, 0, 0)
+}
+
+fn set_rlimit(
+    resource: libc::__rlimit_resource_t,
+    soft: u64,
+    hard: u64,
+) -> SandboxResult<()> {
+    let rlimit = libc::rlimit { rlim_cur: soft, rlim_max: hard };
+    if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+        return Err(setup_error("setrlimit")(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+// Closes every fd but the kept ones.
+fn close_fds(keep: &[RawFd]) -> io::Result<()> {
+    let mut keep = keep.to_vec();
+    keep.sort_unstable();
+    let mut first = 0;
+    for &fd in keep.iter() {
+        if fd > first {
+            close_range(first, fd - 1)?;
+        }
+        first = first.max(fd + 1);
+    }
+    close_range(first, RawFd::MAX)
+}
+
+fn close_range(first: RawFd, last: RawFd) -> io::Result<()> {
+    let closed = unsafe {
+        libc::syscall(libc::SYS_close_range, first as libc::c_uint,
+                      last as libc::c_uint, 0 as libc::c_uint)
+    };
+    if closed == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(error);
+    }
+    // Kernels before 5.9.
+    let fds = fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect::<Vec<RawFd>>();
+    for fd in fds {
+        if fd >= first && fd <= last {
+            let _ = unistd::close(fd);
+        }
+    }
+    Ok(())
+}
+
+// Keeps 0 to 2 taken, so that no fd opened later is mistaken for them.
+fn reopen_std_fds() -> io::Result<()> {
+    loop {
+        let fd = fcntl::open("/dev/null", OFlag::O_RDWR, Mode::empty())
+            .map_err(io::Error::other)?;
+        if fd > 2 {
+            return unistd::close(fd).map_err(io::Error::other);
+        }
+    }
+}
+
+fn do_cleanup() -> SandboxResult<()> {
+    util::clean_dir(Path::new("/tmp")).map_err(setup_error("clean /tmp"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Read;
     use std::thread;
+    use cgroup;
 
     #[test]
     fn whoami() {
-        let mut sandbox = Sandbox::new();
+        let mut sandbox = Sandbox::new().unwrap();
         let (pin, pout) = Pipe::new();
         let data_thread = thread::spawn(move || {
             let mut data = String::new();
-            pin.into_reader().read_to_string(&mut data).unwrap();
+            pin.into_reader().unwrap().read_to_string(&mut data).unwrap();
             data
         });
-        let status = sandbox.execute(
+        let result = sandbox.execute(
             PathBuf::from("/usr/bin/whoami"),
             Box::new([String::from("whoami")]),
-            default_envs(),
             PathBuf::from("/"),
             Box::new([(pout, Port::stdout())]),
-            None).unwrap();
-        assert_eq!(status, 0);
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
         let data = data_thread.join().unwrap();
         assert_eq!(data, "icebox\n");
         drop(sandbox);
     }
 
+    #[test]
+    fn close_fds() {
+        // Not closed on exec.
+        let (leak_reader, leak_writer) = unistd::pipe().unwrap();
+        let mut sandbox = Sandbox::new().unwrap();
+        let (pin, pout) = Pipe::new();
+        let data_thread = thread::spawn(move || {
+            let mut data = String::new();
+            pin.into_reader().unwrap().read_to_string(&mut data).unwrap();
+            data
+        });
+        let result = sandbox.execute(
+            PathBuf::from("/bin/ls"),
+            Box::new([String::from("ls"), String::from("/proc/self/fd")]),
+            PathBuf::from("/"),
+            Box::new([(pout, Port::stdout())]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        // The last one is opened by ls itself.
+        assert_eq!(data_thread.join().unwrap(), "0\n1\n2\n3\n");
+        unistd::close(leak_reader).unwrap();
+        unistd::close(leak_writer).unwrap();
+    }
+
+    #[test]
+    fn leftover_processes() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("sleep 10 & sleep 10 & true")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        assert_eq!(result.leftover_processes, 2);
+        let result = sandbox.execute(
+            PathBuf::from("/bin/true"),
+            Box::new([String::from("true")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert_eq!(result.leftover_processes, 0);
+    }
+
+    #[test]
+    fn custom_config() {
+        let extra_dir = TempDir::new("jd-extra").unwrap();
+        File::create(extra_dir.path().join("file")).unwrap()
+            .write_all(b"extra\n").unwrap();
+        let config = SandboxConfig {
+            binds: Box::new([Bind::new(extra_dir.path().to_path_buf(),
+                                       PathBuf::from("opt/extra"),
+                                       AccessMode::ReadOnly).unwrap()]),
+            hostname: String::from("judge"),
+            guest_name: String::from("judge"),
+            guest_uid: 2000,
+            ..Default::default()
+        };
+        let mut sandbox = Sandbox::with_config(&config).unwrap();
+        let (pin, pout) = Pipe::new();
+        let data_thread = thread::spawn(move || {
+            let mut data = String::new();
+            pin.into_reader().unwrap().read_to_string(&mut data).unwrap();
+            data
+        });
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("cat /proc/sys/kernel/hostname; id -u; \
+                                    whoami; cat /opt/extra/file")]),
+            PathBuf::from("/"),
+            Box::new([(pout, Port::stdout())]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        assert_eq!(data_thread.join().unwrap(),
+                   "judge\n2000\njudge\nextra\n");
+    }
+
+    #[test]
+    fn load_config() {
+        let builtin = SandboxConfig::builtin();
+        assert_eq!(builtin.hostname, "icebox");
+        assert_eq!(builtin.binds[0].target, Path::new("var/lib/ghc"));
+        let config = SandboxConfig::load(&b"\
+            binds: [{source: /opt/jdk, target: opt/jdk, mode: read-only}]\n\
+            tmpfs_size: 1048576\n"[..]).unwrap();
+        assert_eq!(config.binds[0].mode, AccessMode::ReadOnly);
+        assert_eq!(config.tmpfs_size, 1048576);
+        assert_eq!(config.devices, default_devices());
+        let load = |text: &str| SandboxConfig::load(text.as_bytes());
+        assert!(load("binds: [{source: /opt, target: /opt, mode: read-only}]")
+            .is_err());
+        assert!(load("binds: [{source: /, target: ../x, mode: read-write}]")
+            .is_err());
+        assert!(load("devices: [/etc/passwd]").is_err());
+        assert!(load("guest_name: 'a:b'").is_err());
+        assert!(load("cgroup_root: /sys/fs/cgroup").is_err());
+        assert!(load("cgroup_root: ../sandbox").is_err());
+        assert!(Bind::new(PathBuf::from("opt"), PathBuf::from("opt"),
+                          AccessMode::ReadOnly).is_err());
+        assert!(Bind::new(PathBuf::from("/opt"), PathBuf::from("/opt"),
+                          AccessMode::ReadOnly).is_err());
+        assert!(load("hostnme: judge").is_err());
+    }
+
     #[test]
     fn read_only() {
-        let mut sandbox = Sandbox::new();
-        let status = sandbox.execute(
+        let mut sandbox = Sandbox::new().unwrap();
+        let result = sandbox.execute(
             PathBuf::from("/usr/bin/test"),
             Box::new([String::from("test"), String::from("-w"), String::from("/bin")]),
-            default_envs(),
             PathBuf::from("/"),
             Box::new([]),
-            None).unwrap();
-        assert_ne!(status, 0);
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert!(!result.exit_status.success());
+    }
+
+    #[test]
+    fn redirect_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        File::create(sandbox.in_dir().join("input")).unwrap()
+            .write_all(b"hello\n").unwrap();
+        File::create(sandbox.out_dir().join("output")).unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/cat"),
+            Box::new([String::from("cat")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([(PathBuf::from("/in/input"), Port::stdin()),
+                      (PathBuf::from("/out/output"), Port::stdout())]),
+            ExecuteOptions::default()).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        let mut data = String::new();
+        File::open(sandbox.out_dir().join("output")).unwrap()
+            .read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello\n");
+    }
+
+    #[test]
+    fn cpu_time_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("while :; do :; done")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                limits: Limits {
+                    cpu_time: Some(Duration::from_millis(200)),
+                    wall_time: Some(Duration::from_secs(10)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.limit_exceeded, Some(Limit::CpuTime));
+        assert_eq!(result.exit_status, ExitStatus::Signaled(libc::SIGKILL));
+        assert!(result.cpu_time >= Duration::from_millis(200));
+        assert!(result.wall_time < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn wall_time_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("sleep 10 & sleep 10")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                limits: Limits {
+                    cpu_time: Some(Duration::from_secs(1)),
+                    wall_time: Some(Duration::from_millis(200)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.limit_exceeded, Some(Limit::WallTime));
+        assert!(result.wall_time < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn file_size_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        File::create(sandbox.out_dir().join("output")).unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/usr/bin/yes"),
+            Box::new([String::from("yes")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([(PathBuf::from("/out/output"), Port::stdout())]),
+            ExecuteOptions {
+                limits: Limits { file_size: Some(1024), ..Default::default() },
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.limit_exceeded, Some(Limit::Output));
+        let metadata = fs::metadata(sandbox.out_dir().join("output")).unwrap();
+        assert_eq!(metadata.len(), 1024);
+    }
+
+    #[test]
+    fn stack_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let (pin, pout) = Pipe::new();
+        let data_thread = thread::spawn(move || {
+            let mut data = String::new();
+            pin.into_reader().unwrap().read_to_string(&mut data).unwrap();
+            data
+        });
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("ulimit -s; ulimit -c")]),
+            PathBuf::from("/"),
+            Box::new([(pout, Port::stdout())]),
+            Box::new([]),
+            ExecuteOptions {
+                limits: Limits { stack: Some(UNLIMITED), ..Default::default() },
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        assert_eq!(data_thread.join().unwrap(), "unlimited\n0\n");
+    }
+
+    #[test]
+    fn cgroup_pids_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut cgroup = CGroup::new_in(sandbox.cgroup_root()).unwrap();
+        cgroup.set_limits(&cgroup::Limits {
+            pids: Some(1),
+            ..Default::default()
+        }).unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("/bin/true; /bin/true")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([(PathBuf::from("/dev/null"), Port::stderr())]),
+            ExecuteOptions {
+                cgroup: Some(&mut cgroup),
+                ..Default::default()
+            }).unwrap();
+        assert!(!result.exit_status.success());
+    }
+
+    #[test]
+    fn usage_without_cgroup() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("i=0; while [ $i -lt 100000 ]; do \
+                                    i=$((i+1)); done")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                limits: Limits {
+                    processes: Some(64),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        assert!(result.usage.is_none());
+        assert!(result.cpu_time > Duration::from_millis(10));
+        assert!(result.memory > 0);
+    }
+
+    #[test]
+    fn cgroup_usage() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut cgroup = CGroup::new_in(sandbox.cgroup_root()).unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("i=0; while [ $i -lt 100000 ]; do \
+                                    i=$((i+1)); done & wait")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                cgroup: Some(&mut cgroup),
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        let usage = result.usage.unwrap();
+        assert!(usage.cpu_time > Duration::from_millis(10));
+        assert_eq!(usage.pids, 0);
+    }
+
+    #[test]
+    fn cgroup_memory_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut cgroup = CGroup::new_in(sandbox.cgroup_root()).unwrap();
+        cgroup.set_limits(&cgroup::Limits {
+            memory: Some(64 << 20),
+            ..Default::default()
+        }).unwrap();
+        // Doubles a string up to 128 MB.
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("a=x; i=0; while [ $i -lt 27 ]; do \
+                                    a=$a$a; i=$((i+1)); done")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                limits: Limits {
+                    wall_time: Some(Duration::from_secs(10)),
+                    ..Default::default()
+                },
+                cgroup: Some(&mut cgroup),
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.limit_exceeded, Some(Limit::Memory));
+        assert_eq!(result.exit_status, ExitStatus::Signaled(libc::SIGKILL));
+    }
+
+    #[test]
+    fn cgroup_cpu_time_limit() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut cgroup = CGroup::new_in(sandbox.cgroup_root()).unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("while :; do :; done & \
+                                    while :; do :; done & wait")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                limits: Limits {
+                    cpu_time: Some(Duration::from_millis(200)),
+                    wall_time: Some(Duration::from_secs(10)),
+                    ..Default::default()
+                },
+                cgroup: Some(&mut cgroup),
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.limit_exceeded, Some(Limit::CpuTime));
+        // Before the rlimit of either worker.
+        assert!(result.wall_time < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cgroup_kill_all() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut cgroup = CGroup::new_in(sandbox.cgroup_root()).unwrap();
+        let result = sandbox.execute(
+            PathBuf::from("/bin/sh"),
+            Box::new([String::from("sh"),
+                      String::from("-c"),
+                      String::from("sleep 10 & sleep 10 &")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions {
+                cgroup: Some(&mut cgroup),
+                ..Default::default()
+            }).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Exited(0));
+        // The sandbox already killed the leftovers.
+        assert_eq!(result.leftover_processes, 2);
+        assert!(cgroup.procs().unwrap().is_empty());
+        cgroup.kill_all().unwrap();
+        assert!(cgroup.procs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn exec_error_with_many_files() {
+        let mut sandbox = Sandbox::new().unwrap();
+        File::create(sandbox.in_dir().join("input")).unwrap();
+        // Likely to cover the fd of the error pipe.
+        let files = (3..16).map(|fd| {
+            (PathBuf::from("/in/input"),
+             Port(String::from("input"), fd, OFlag::O_RDONLY))
+        }).collect::<Vec<_>>();
+        let error = sandbox.execute(
+            PathBuf::from("/bin/nonexistent"),
+            Box::new([String::from("nonexistent")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            files.into_boxed_slice(),
+            ExecuteOptions::default()).unwrap_err();
+        assert!(matches!(error, SandboxError::Exec(_)));
+    }
+
+    #[test]
+    fn exec_not_found() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let error = sandbox.execute(
+            PathBuf::from("/bin/nonexistent"),
+            Box::new([String::from("nonexistent")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap_err();
+        match error {
+            SandboxError::Exec(ref message) => assert!(message.contains("ENOENT")),
+            e => panic!("{:?}", e),
+        }
+        let result = sandbox.execute(
+            PathBuf::from("/bin/true"),
+            Box::new([String::from("true")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap();
+        assert!(result.exit_status.success());
+    }
+
+    #[test]
+    fn open_not_found() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let mut cgroup = CGroup::new_in(sandbox.cgroup_root()).unwrap();
+        let error = sandbox.execute(
+            PathBuf::from("/bin/cat"),
+            Box::new([String::from("cat")]),
+            PathBuf::from("/"),
+            Box::new([]),
+            Box::new([(PathBuf::from("/in/nonexistent"), Port::stdin())]),
+            ExecuteOptions {
+                cgroup: Some(&mut cgroup),
+                ..Default::default()
+            }).unwrap_err();
+        assert!(matches!(error, SandboxError::Setup(_)));
+        sandbox.cleanup().unwrap();
+    }
+
+    #[test]
+    fn fifo_name_taken() {
+        let mut sandbox = Sandbox::new().unwrap();
+        let (pin, _) = Pipe::new();
+        let (pout, _) = Pipe::new();
+        let error = sandbox.execute(
+            PathBuf::from("/bin/true"),
+            Box::new([String::from("true")]),
+            PathBuf::from("/"),
+            Box::new([
+                (pin, Port(String::from("pipe"), 3, OFlag::O_RDONLY)),
+                (pout, Port(String::from("pipe"), 4, OFlag::O_WRONLY)),
+            ]),
+            Box::new([]),
+            ExecuteOptions::default()).unwrap_err();
+        assert!(matches!(error, SandboxError::Runtime(_)));
+        sandbox.cleanup().unwrap();
     }
 }
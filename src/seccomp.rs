@@ -0,0 +1,274 @@
+use std::io;
+use std::mem;
+use libc;
+
+// Denied unless a profile says otherwise. These reach into the kernel or
+// other processes in ways a submission never needs.
+const DEFAULT_DENIED: &[&str] = &[
+    "ptrace", "process_vm_readv", "process_vm_writev", "kcmp",
+    "keyctl", "add_key", "request_key", "bpf", "perf_event_open",
+    "userfaultfd", "unshare", "setns", "mount", "umount2", "pivot_root",
+    "chroot", "open_tree", "move_mount", "fsopen", "fsconfig", "fsmount",
+    "fspick", "mount_setattr", "open_by_handle_at", "name_to_handle_at",
+    "kexec_load", "kexec_file_load", "init_module", "finit_module",
+    "delete_module", "reboot", "swapon", "swapoff", "acct", "quotactl",
+    "syslog", "settimeofday", "clock_settime", "adjtimex", "clock_adjtime",
+    "iopl", "ioperm", "lookup_dcookie", "fanotify_init",
+];
+
+// The system calls are only listed for x86_64. Elsewhere a profile cannot be
+// installed, rather than filtering by the wrong numbers.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(not(target_arch = "x86_64"))]
+const AUDIT_ARCH: Option<u32> = None;
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+// Offsets into struct seccomp_data.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const ARG0_OFFSET: u32 = 16;
+const CLONE_NAMESPACES: u32 = (libc::CLONE_NEWNS | libc::CLONE_NEWCGROUP |
+    libc::CLONE_NEWUTS | libc::CLONE_NEWIPC | libc::CLONE_NEWUSER |
+    libc::CLONE_NEWPID | libc::CLONE_NEWNET) as u32;
+
+// Which system calls a program may make. Calls which are not allowed kill the
+// process with SIGSYS. Whatever the profile, clone is denied with namespace
+// flags, and clone3, whose flags cannot be inspected, fails with ENOSYS so
+// that the C library falls back to clone.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    default_allowed: bool,
+    // Take the opposite of the default action.
+    syscalls: Box<[u32]>,
+}
+
+impl Profile {
+    // Allows only the named system calls, and execve, which is how the
+    // program starts.
+    pub fn allow(names: &[String]) -> Result<Profile, String> {
+        let mut syscalls = syscall_numbers(names)?;
+        syscalls.push(libc::SYS_execve as u32);
+        Ok(Profile { default_allowed: false, syscalls: dedup(syscalls) })
+    }
+
+    // Allows everything but the named system calls.
+    pub fn deny(names: &[String]) -> Result<Profile, String> {
+        let syscalls = syscall_numbers(names)?;
+        Ok(Profile { default_allowed: true, syscalls: dedup(syscalls) })
+    }
+
+    // Installs the filter on the calling thread, which can no longer gain
+    // privileges.
+    pub(crate) fn install(&self) -> io::Result<()> {
+        let arch = AUDIT_ARCH.ok_or_else(
+            || io::Error::other("unsupported architecture"))?;
+        let mut filter = self.filter(arch);
+        let program = libc::sock_fprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_mut_ptr(),
+        };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER,
+                           &program as *const libc::sock_fprog) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn filter(&self, arch: u32) -> Vec<libc::sock_filter> {
+        let (default_action, listed_action) = if self.default_allowed {
+            (libc::SECCOMP_RET_ALLOW, libc::SECCOMP_RET_KILL_PROCESS)
+        } else {
+            (libc::SECCOMP_RET_KILL_PROCESS, libc::SECCOMP_RET_ALLOW)
+        };
+        let mut filter = vec![
+            load(ARCH_OFFSET),
+            jump(libc::BPF_JEQ, arch, 1, 0),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            load(NR_OFFSET),
+            jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            jump(libc::BPF_JEQ, libc::SYS_clone3 as u32, 0, 1),
+            ret(libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32),
+            jump(libc::BPF_JEQ, libc::SYS_clone as u32, 0, 3),
+            load(ARG0_OFFSET),
+            jump(libc::BPF_JSET, CLONE_NAMESPACES, 0, 1),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            load(NR_OFFSET),
+        ];
+        for &syscall in self.syscalls.iter() {
+            filter.push(jump(libc::BPF_JEQ, syscall, 0, 1));
+            filter.push(ret(listed_action));
+        }
+        filter.push(ret(default_action));
+        filter
+    }
+}
+
+// Names without a number on this architecture are skipped, as the profile is
+// never installed there.
+impl Default for Profile {
+    fn default() -> Profile {
+        let syscalls = DEFAULT_DENIED.iter()
+            .filter_map(|name| syscall_number(name)).collect();
+        Profile { default_allowed: true, syscalls: dedup(syscalls) }
+    }
+}
+
+fn load(offset: u32) -> libc::sock_filter {
+    statement((libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16, offset)
+}
+
+fn jump(op: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn ret(action: u32) -> libc::sock_filter {
+    statement((libc::BPF_RET | libc::BPF_K) as u16, action)
+}
+
+fn statement(code: u16, k: u32) -> libc::sock_filter {
+    let mut filter: libc::sock_filter = unsafe { mem::zeroed() };
+    filter.code = code;
+    filter.k = k;
+    filter
+}
+
+fn syscall_numbers(names: &[String]) -> Result<Vec<u32>, String> {
+    names.iter().map(|name| {
+        syscall_number(name).ok_or_else(|| format!("unknown syscall {}", name))
+    }).collect()
+}
+
+fn dedup(mut syscalls: Vec<u32>) -> Box<[u32]> {
+    syscalls.sort();
+    syscalls.dedup();
+    syscalls.into_boxed_slice()
+}
+
+macro_rules! syscall_table {
+    ($($name:ident,)*) => {
+        fn syscall_number(name: &str) -> Option<u32> {
+            match format!("SYS_{}", name).as_str() {
+                $(stringify!($name) => Some(libc::$name as u32),)*
+                _ => None,
+            }
+        }
+    }
+}
+
+// The x86_64 system calls known to the libc crate.
+#[cfg(target_arch = "x86_64")]
+syscall_table! {
+    SYS_read, SYS_write, SYS_open, SYS_close, SYS_stat, SYS_fstat, SYS_lstat,
+    SYS_poll, SYS_lseek, SYS_mmap, SYS_mprotect, SYS_munmap, SYS_brk,
+    SYS_rt_sigaction, SYS_rt_sigprocmask, SYS_rt_sigreturn, SYS_ioctl,
+    SYS_pread64, SYS_pwrite64, SYS_readv, SYS_writev, SYS_access, SYS_pipe,
+    SYS_select, SYS_sched_yield, SYS_mremap, SYS_msync, SYS_mincore,
+    SYS_madvise, SYS_shmget, SYS_shmat, SYS_shmctl, SYS_dup, SYS_dup2,
+    SYS_pause, SYS_nanosleep, SYS_getitimer, SYS_alarm, SYS_setitimer,
+    SYS_getpid, SYS_sendfile, SYS_socket, SYS_connect, SYS_accept, SYS_sendto,
+    SYS_recvfrom, SYS_sendmsg, SYS_recvmsg, SYS_shutdown, SYS_bind, SYS_listen,
+    SYS_getsockname, SYS_getpeername, SYS_socketpair, SYS_setsockopt,
+    SYS_getsockopt, SYS_clone, SYS_fork, SYS_vfork, SYS_execve, SYS_exit,
+    SYS_wait4, SYS_kill, SYS_uname, SYS_semget, SYS_semop, SYS_semctl,
+    SYS_shmdt, SYS_msgget, SYS_msgsnd, SYS_msgrcv, SYS_msgctl, SYS_fcntl,
+    SYS_flock, SYS_fsync, SYS_fdatasync, SYS_truncate, SYS_ftruncate,
+    SYS_getdents, SYS_getcwd, SYS_chdir, SYS_fchdir, SYS_rename, SYS_mkdir,
+    SYS_rmdir, SYS_creat, SYS_link, SYS_unlink, SYS_symlink, SYS_readlink,
+    SYS_chmod, SYS_fchmod, SYS_chown, SYS_fchown, SYS_lchown, SYS_umask,
+    SYS_gettimeofday, SYS_getrlimit, SYS_getrusage, SYS_sysinfo, SYS_times,
+    SYS_ptrace, SYS_getuid, SYS_syslog, SYS_getgid, SYS_setuid, SYS_setgid,
+    SYS_geteuid, SYS_getegid, SYS_setpgid, SYS_getppid, SYS_getpgrp, SYS_setsid,
+    SYS_setreuid, SYS_setregid, SYS_getgroups, SYS_setgroups, SYS_setresuid,
+    SYS_getresuid, SYS_setresgid, SYS_getresgid, SYS_getpgid, SYS_setfsuid,
+    SYS_setfsgid, SYS_getsid, SYS_capget, SYS_capset, SYS_rt_sigpending,
+    SYS_rt_sigtimedwait, SYS_rt_sigqueueinfo, SYS_rt_sigsuspend,
+    SYS_sigaltstack, SYS_utime, SYS_mknod, SYS_uselib, SYS_personality,
+    SYS_ustat, SYS_statfs, SYS_fstatfs, SYS_sysfs, SYS_getpriority,
+    SYS_setpriority, SYS_sched_setparam, SYS_sched_getparam,
+    SYS_sched_setscheduler, SYS_sched_getscheduler, SYS_sched_get_priority_max,
+    SYS_sched_get_priority_min, SYS_sched_rr_get_interval, SYS_mlock,
+    SYS_munlock, SYS_mlockall, SYS_munlockall, SYS_vhangup, SYS_modify_ldt,
+    SYS_pivot_root, SYS__sysctl, SYS_prctl, SYS_arch_prctl, SYS_adjtimex,
+    SYS_setrlimit, SYS_chroot, SYS_sync, SYS_acct, SYS_settimeofday, SYS_mount,
+    SYS_umount2, SYS_swapon, SYS_swapoff, SYS_reboot, SYS_sethostname,
+    SYS_setdomainname, SYS_iopl, SYS_ioperm, SYS_init_module, SYS_delete_module,
+    SYS_quotactl, SYS_nfsservctl, SYS_getpmsg, SYS_putpmsg, SYS_afs_syscall,
+    SYS_tuxcall, SYS_security, SYS_gettid, SYS_readahead, SYS_setxattr,
+    SYS_lsetxattr, SYS_fsetxattr, SYS_getxattr, SYS_lgetxattr, SYS_fgetxattr,
+    SYS_listxattr, SYS_llistxattr, SYS_flistxattr, SYS_removexattr,
+    SYS_lremovexattr, SYS_fremovexattr, SYS_tkill, SYS_time, SYS_futex,
+    SYS_sched_setaffinity, SYS_sched_getaffinity, SYS_set_thread_area,
+    SYS_io_setup, SYS_io_destroy, SYS_io_getevents, SYS_io_submit,
+    SYS_io_cancel, SYS_get_thread_area, SYS_lookup_dcookie, SYS_epoll_create,
+    SYS_epoll_ctl_old, SYS_epoll_wait_old, SYS_remap_file_pages, SYS_getdents64,
+    SYS_set_tid_address, SYS_restart_syscall, SYS_semtimedop, SYS_fadvise64,
+    SYS_timer_create, SYS_timer_settime, SYS_timer_gettime,
+    SYS_timer_getoverrun, SYS_timer_delete, SYS_clock_settime,
+    SYS_clock_gettime, SYS_clock_getres, SYS_clock_nanosleep, SYS_exit_group,
+    SYS_epoll_wait, SYS_epoll_ctl, SYS_tgkill, SYS_utimes, SYS_vserver,
+    SYS_mbind, SYS_set_mempolicy, SYS_get_mempolicy, SYS_mq_open, SYS_mq_unlink,
+    SYS_mq_timedsend, SYS_mq_timedreceive, SYS_mq_notify, SYS_mq_getsetattr,
+    SYS_kexec_load, SYS_waitid, SYS_add_key, SYS_request_key, SYS_keyctl,
+    SYS_ioprio_set, SYS_ioprio_get, SYS_inotify_init, SYS_inotify_add_watch,
+    SYS_inotify_rm_watch, SYS_migrate_pages, SYS_openat, SYS_mkdirat,
+    SYS_mknodat, SYS_fchownat, SYS_futimesat, SYS_newfstatat, SYS_unlinkat,
+    SYS_renameat, SYS_linkat, SYS_symlinkat, SYS_readlinkat, SYS_fchmodat,
+    SYS_faccessat, SYS_pselect6, SYS_ppoll, SYS_unshare, SYS_set_robust_list,
+    SYS_get_robust_list, SYS_splice, SYS_tee, SYS_sync_file_range, SYS_vmsplice,
+    SYS_move_pages, SYS_utimensat, SYS_epoll_pwait, SYS_signalfd,
+    SYS_timerfd_create, SYS_eventfd, SYS_fallocate, SYS_timerfd_settime,
+    SYS_timerfd_gettime, SYS_accept4, SYS_signalfd4, SYS_eventfd2,
+    SYS_epoll_create1, SYS_dup3, SYS_pipe2, SYS_inotify_init1, SYS_preadv,
+    SYS_pwritev, SYS_rt_tgsigqueueinfo, SYS_perf_event_open, SYS_recvmmsg,
+    SYS_fanotify_init, SYS_fanotify_mark, SYS_prlimit64, SYS_name_to_handle_at,
+    SYS_open_by_handle_at, SYS_clock_adjtime, SYS_syncfs, SYS_sendmmsg,
+    SYS_setns, SYS_getcpu, SYS_process_vm_readv, SYS_process_vm_writev,
+    SYS_kcmp, SYS_finit_module, SYS_sched_setattr, SYS_sched_getattr,
+    SYS_renameat2, SYS_seccomp, SYS_getrandom, SYS_memfd_create,
+    SYS_kexec_file_load, SYS_bpf, SYS_execveat, SYS_userfaultfd, SYS_membarrier,
+    SYS_mlock2, SYS_copy_file_range, SYS_preadv2, SYS_pwritev2,
+    SYS_pkey_mprotect, SYS_pkey_alloc, SYS_pkey_free, SYS_statx, SYS_rseq,
+    SYS_pidfd_send_signal, SYS_io_uring_setup, SYS_io_uring_enter,
+    SYS_io_uring_register, SYS_open_tree, SYS_move_mount, SYS_fsopen,
+    SYS_fsconfig, SYS_fsmount, SYS_fspick, SYS_pidfd_open, SYS_clone3,
+    SYS_close_range, SYS_openat2, SYS_pidfd_getfd, SYS_faccessat2,
+    SYS_process_madvise, SYS_epoll_pwait2, SYS_mount_setattr, SYS_quotactl_fd,
+    SYS_landlock_create_ruleset, SYS_landlock_add_rule,
+    SYS_landlock_restrict_self, SYS_memfd_secret, SYS_process_mrelease,
+    SYS_futex_waitv, SYS_set_mempolicy_home_node, SYS_fchmodat2, SYS_mseal,
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn syscall_number(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        let names = |names: &[&str]| {
+            names.iter().map(|name| name.to_string()).collect::<Vec<_>>()
+        };
+        let profile = Profile::deny(&names(&["ptrace", "bpf", "ptrace"]))
+            .unwrap();
+        assert_eq!(profile.syscalls.len(), 2);
+        let profile = Profile::allow(&names(&["read", "write"])).unwrap();
+        assert!(profile.syscalls.contains(&(libc::SYS_execve as u32)));
+        assert!(Profile::deny(&names(&["ptrace", "fly"])).is_err());
+        assert_eq!(Profile::default().syscalls.len(), DEFAULT_DENIED.len());
+    }
+}
@@ -1,11 +1,14 @@
 use std::fs;
+use std::io;
 use std::path::Path;
 use std::sync::Mutex;
 use std::sync::mpsc::{self, Receiver, Sender};
 
+// None marks a lost item. Once one is lost, getters which come to it fail
+// instead of waiting, and put it back for the next ones.
 pub struct Pool<T> {
-    tx: Sender<T>,
-    rx: Mutex<Receiver<T>>,
+    tx: Sender<Option<T>>,
+    rx: Mutex<Receiver<Option<T>>>,
 }
 
 impl<T> Pool<T> {
@@ -15,46 +18,78 @@ impl<T> Pool<T> {
     }
 
     pub fn put(&self, item: T) {
-        self.tx.send(item).unwrap();
+        self.tx.send(Some(item)).unwrap();
     }
 
-    pub fn get_one(&self) -> T {
+    // Gives up an item which could not be put back.
+    pub fn lose(&self) {
+        self.tx.send(None).unwrap();
+    }
+
+    pub fn get_one(&self) -> Option<T> {
         let rx = self.rx.lock().unwrap();
-        rx.recv().unwrap()
+        let item = rx.recv().unwrap();
+        if item.is_none() {
+            self.lose();
+        }
+        item
     }
 
-    pub fn get_two(&self) -> (T, T) {
+    pub fn get_two(&self) -> Option<(T, T)> {
         let rx = self.rx.lock().unwrap();
-        (rx.recv().unwrap(), rx.recv().unwrap())
+        let first = rx.recv().unwrap();
+        let second = match first {
+            Some(_) => rx.recv().unwrap(),
+            None => None,
+        };
+        match (first, second) {
+            (Some(first), Some(second)) => Some((first, second)),
+            (first, _) => {
+                if let Some(first) = first {
+                    self.put(first);
+                }
+                self.lose();
+                None
+            },
+        }
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Pool<T> {
+        Pool::new()
     }
 }
 
-pub fn copy_dir(from: &Path, to: &Path) {
-    for result in fs::read_dir(from).unwrap() {
-        let entry = result.unwrap();
-        let file_type = entry.file_type().unwrap();
+pub fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for result in fs::read_dir(from)? {
+        let entry = result?;
+        let file_type = entry.file_type()?;
         let inner_from = entry.path();
-        let inner_to = to.join(&entry.file_name());
+        let inner_to = to.join(entry.file_name());
         if file_type.is_dir() {
-            copy_dir(&inner_from, &inner_to);
+            fs::create_dir(&inner_to)?;
+            copy_dir(&inner_from, &inner_to)?;
         } else {
-            fs::copy(&inner_from, &inner_to).unwrap();
+            fs::copy(&inner_from, &inner_to)?;
         }
     }
+    Ok(())
 }
 
-pub fn clean_dir(path: &Path) {
-    for result in fs::read_dir(path).unwrap() {
-        let entry = result.unwrap();
-        let file_type = entry.file_type().unwrap();
+pub fn clean_dir(path: &Path) -> io::Result<()> {
+    for result in fs::read_dir(path)? {
+        let entry = result?;
+        let file_type = entry.file_type()?;
         let inner_path = entry.path();
         if file_type.is_dir() {
-            clean_dir(&inner_path);
-            fs::remove_dir(&inner_path).unwrap();
+            clean_dir(&inner_path)?;
+            fs::remove_dir(&inner_path)?;
         } else {
-            fs::remove_file(&inner_path).unwrap();
+            fs::remove_file(&inner_path)?;
         }
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -67,7 +102,7 @@ mod tests {
         pool.put("A");
         pool.put("B");
         let one = pool.get_one();
-        assert_eq!(one, "A");
+        assert_eq!(one, Some("A"));
     }
 
     #[test]
@@ -76,6 +111,17 @@ mod tests {
         pool.put("A");
         pool.put("B");
         let two = pool.get_two();
-        assert_eq!(two, ("A", "B"));
+        assert_eq!(two, Some(("A", "B")));
+    }
+
+    #[test]
+    fn pool_lost() {
+        let pool = Pool::new();
+        pool.put("A");
+        pool.lose();
+        assert_eq!(pool.get_two(), None);
+        assert_eq!(pool.get_one(), Some("A"));
+        assert_eq!(pool.get_one(), None);
+        assert_eq!(pool.get_one(), None);
     }
 }
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::time::Duration;
+
+const MAX_MESSAGE_LEN: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Accepted,
+    WrongAnswer,
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+    RuntimeError,
+    // Made a system call denied by the seccomp profile.
+    RestrictedFunction,
+    OutputLimitExceeded,
+    CompileError,
+    SystemError,
+    // Not judged, because of an earlier failure in the subtask or in a
+    // subtask it depends on.
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i32),
+    Signaled(i32),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub status: Status,
+    pub score: i32,
+    pub time: Duration,
+    pub memory: usize,
+    pub exit_status: Option<ExitStatus>,
+    pub message: String,
+}
+
+impl Status {
+    pub fn short_name(self) -> &'static str {
+        match self {
+            Status::Accepted => "AC",
+            Status::WrongAnswer => "WA",
+            Status::TimeLimitExceeded => "TLE",
+            Status::MemoryLimitExceeded => "MLE",
+            Status::RuntimeError => "RE",
+            Status::RestrictedFunction => "RF",
+            Status::OutputLimitExceeded => "OLE",
+            Status::CompileError => "CE",
+            Status::SystemError => "SE",
+            Status::Skipped => "SKIP",
+        }
+    }
+}
+
+impl ExitStatus {
+    pub fn success(self) -> bool {
+        self == ExitStatus::Exited(0)
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExitStatus::Exited(code) => write!(f, "exited with code {}", code),
+            ExitStatus::Signaled(signal) =>
+                write!(f, "killed by signal {}", signal),
+        }
+    }
+}
+
+impl Verdict {
+    pub fn new(status: Status) -> Verdict {
+        Verdict {
+            status,
+            score: 0,
+            time: Duration::from_secs(0),
+            memory: 0,
+            exit_status: None,
+            message: String::new(),
+        }
+    }
+
+    pub fn with_message(mut self, message: &str) -> Verdict {
+        let mut len = message.len().min(MAX_MESSAGE_LEN);
+        while !message.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.message = String::from(&message[..len]);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn truncate_message() {
+        let message = "\u{4e2d}".repeat(MAX_MESSAGE_LEN);
+        let verdict = Verdict::new(Status::CompileError).with_message(&message);
+        assert!(verdict.message.len() <= MAX_MESSAGE_LEN);
+        assert!(message.starts_with(&verdict.message));
+    }
+
+    #[test]
+    fn serialize() {
+        let mut verdict = Verdict::new(Status::RuntimeError)
+            .with_message("segmentation fault");
+        verdict.time = Duration::from_millis(15);
+        verdict.memory = 1048576;
+        verdict.exit_status = Some(ExitStatus::Signaled(11));
+        let text = serde_yaml::to_string(&verdict).unwrap();
+        let parsed: Verdict = serde_yaml::from_str(&text).unwrap();
+        assert_eq!(parsed, verdict);
+    }
+}